@@ -0,0 +1,164 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+
+use crate::state::{RunRecord, RunStatus};
+
+/// Totals over the run history, written every `analytics_report_after_runs` runs
+#[derive(Debug, Serialize, PartialEq)]
+pub struct AnalyticsSummary {
+    pub generated_at: DateTime<Utc>,
+    pub runs: usize,
+    pub first_run: Option<DateTime<Utc>>,
+    pub last_run: Option<DateTime<Utc>>,
+    pub prs_created: usize,
+    pub prs_merged: usize,
+    pub files_changed: usize,
+    pub lines_added: usize,
+    pub lines_removed: usize,
+    /// Average time from opening a PR to merging it, over the PRs where both are known
+    pub average_pr_open_secs: Option<i64>,
+    /// Weekday with the most PRs, e.g. "Tue"
+    pub most_active_day: Option<String>,
+    /// Hour of the day (UTC) with the most PRs
+    pub most_active_hour: Option<u32>,
+}
+
+/// Summarize the runs in the history
+pub fn summarize(history: &[RunRecord], now: DateTime<Utc>) -> AnalyticsSummary {
+    let with_pr: Vec<&RunRecord> = history
+        .iter()
+        .filter(|run| run.status == RunStatus::Succeeded && run.pr_number.is_some())
+        .collect();
+
+    let open_times: Vec<i64> = with_pr.iter().filter_map(|run| run.pr_open_secs).collect();
+    let average_pr_open_secs = if open_times.is_empty() {
+        None
+    } else {
+        Some(open_times.iter().sum::<i64>() / open_times.len() as i64)
+    };
+
+    AnalyticsSummary {
+        generated_at: now,
+        runs: history.len(),
+        first_run: history.iter().map(|run| run.started_at).min(),
+        last_run: history.iter().map(|run| run.started_at).max(),
+        prs_created: with_pr.len(),
+        prs_merged: with_pr.iter().filter(|run| run.merged).count(),
+        files_changed: history.iter().map(|run| run.files_changed).sum(),
+        // Runs recorded before diff stats were kept only know the lines they wrote
+        lines_added: history.iter().map(|run| run.lines_added.unwrap_or(run.lines_changed)).sum(),
+        lines_removed: history.iter().filter_map(|run| run.lines_deleted).sum(),
+        average_pr_open_secs,
+        most_active_day: most_common(with_pr.iter().map(|run| run.started_at.weekday().num_days_from_monday()))
+            .map(|day| WEEKDAYS[day as usize].to_string()),
+        most_active_hour: most_common(with_pr.iter().map(|run| run.started_at.hour())),
+    }
+}
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// The most frequent value, preferring the smallest on ties
+fn most_common(values: impl Iterator<Item = u32>) -> Option<u32> {
+    let mut counts = [0usize; 24];
+    let mut any = false;
+    for value in values {
+        counts[value as usize] += 1;
+        any = true;
+    }
+    if !any {
+        return None;
+    }
+    let max = *counts.iter().max()?;
+    counts.iter().position(|count| *count == max).map(|value| value as u32)
+}
+
+impl fmt::Display for AnalyticsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_na = |value: Option<String>| value.unwrap_or_else(|| "n/a".to_string());
+        writeln!(f, "Activity analytics ({})", self.generated_at.format("%Y-%m-%d %H:%M UTC"))?;
+        writeln!(
+            f,
+            "Runs: {} ({} to {})",
+            self.runs,
+            or_na(self.first_run.map(|at| at.format("%Y-%m-%d").to_string())),
+            or_na(self.last_run.map(|at| at.format("%Y-%m-%d").to_string()))
+        )?;
+        writeln!(f, "PRs created: {}, merged: {}", self.prs_created, self.prs_merged)?;
+        writeln!(f, "Files changed: {}", self.files_changed)?;
+        writeln!(f, "Lines added: {}, removed: {}", self.lines_added, self.lines_removed)?;
+        writeln!(
+            f,
+            "Average PR open time: {}",
+            or_na(self.average_pr_open_secs.map(|secs| format!("{}m {}s", secs / 60, secs % 60)))
+        )?;
+        writeln!(f, "Most active day: {}", or_na(self.most_active_day.clone()))?;
+        write!(f, "Most active hour: {}", or_na(self.most_active_hour.map(|hour| format!("{:02}:00 UTC", hour))))
+    }
+}
+
+/// Write the summary as JSON when `path` ends in `.json`, as text otherwise
+pub fn write_report(path: &Path, summary: &AnalyticsSummary) -> Result<(), Box<dyn std::error::Error>> {
+    let content = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::to_string_pretty(summary)?
+    } else {
+        format!("{}\n", summary)
+    };
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::RunType;
+    use chrono::TimeZone;
+
+    fn run(started_at: DateTime<Utc>, pr_number: Option<u64>, pr_open_secs: Option<i64>) -> RunRecord {
+        RunRecord {
+            run_id: None,
+            run_type: RunType::Pr,
+            started_at,
+            status: if pr_number.is_some() { RunStatus::Succeeded } else { RunStatus::Failed },
+            pr_number,
+            pr_url: None,
+            merged: pr_number.is_some(),
+            closed: false,
+            files_changed: if pr_number.is_some() { 2 } else { 0 },
+            lines_changed: if pr_number.is_some() { 30 } else { 0 },
+            lines_added: None,
+            lines_deleted: None,
+            pr_open_secs,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn summary_totals_the_history() {
+        let now = Utc.with_ymd_and_hms(2024, 5, 10, 12, 0, 0).unwrap();
+        let history = vec![
+            // Tuesday and Wednesday at 09:xx, Thursday at 17:xx
+            run(Utc.with_ymd_and_hms(2024, 5, 7, 9, 15, 0).unwrap(), Some(1), Some(120)),
+            run(Utc.with_ymd_and_hms(2024, 5, 8, 9, 45, 0).unwrap(), Some(2), Some(240)),
+            run(Utc.with_ymd_and_hms(2024, 5, 9, 17, 0, 0).unwrap(), Some(3), None),
+            run(Utc.with_ymd_and_hms(2024, 5, 9, 18, 0, 0).unwrap(), None, None),
+        ];
+
+        let summary = summarize(&history, now);
+
+        assert_eq!(summary.runs, 4);
+        assert_eq!(summary.prs_created, 3);
+        assert_eq!(summary.prs_merged, 3);
+        assert_eq!(summary.files_changed, 6);
+        assert_eq!(summary.lines_added, 90);
+        assert_eq!(summary.average_pr_open_secs, Some(180));
+        assert_eq!(summary.most_active_day.as_deref(), Some("Tue"));
+        assert_eq!(summary.most_active_hour, Some(9));
+        assert!(summary.to_string().contains("Average PR open time: 3m 0s"), "{}", summary);
+
+        let empty = summarize(&[], now);
+        assert_eq!(empty.most_active_day, None);
+        assert!(empty.to_string().contains("Most active hour: n/a"), "{}", empty);
+    }
+}
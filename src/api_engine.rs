@@ -0,0 +1,357 @@
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+use octocrab::models::repos::Content;
+use rand::{rngs::StdRng, Rng};
+use serde_json::{json, Value};
+
+use crate::{changelog, codeowners, compaction, diffstat, readme};
+use crate::{parse_time_window, pick_commit_time, ChangePlan, ChangeSet, GitHubBot, BOTIGNORE_FILE, CHANGES_DIR};
+
+impl GitHubBot {
+    /// Commit new change files on a new branch through the Git Data API, without a local clone.
+    /// Unlike `make_changes`, this never prunes the changes directory.
+    pub(crate) async fn make_changes_via_api(&self, rng: &mut StdRng) -> Result<ChangeSet, Box<dyn std::error::Error>> {
+        let repo_route = format!("/repos/{}/{}", self.repo_owner, self.repo_name);
+        let branch_name = self.new_branch_name(Utc::now(), rng);
+
+        // The branch starts from the default branch's current commit and tree
+        let base_branch = self.default_branch()?;
+        let master: Value = self.api
+            .get(format!("{}/git/ref/heads/{}", repo_route, base_branch))
+            .await?;
+        let base_sha = master["object"]["sha"].as_str().ok_or("master ref has no commit sha")?.to_string();
+        let base_commit: Value = self.api
+            .get(format!("{}/git/commits/{}", repo_route, base_sha))
+            .await?;
+        let base_tree = base_commit["tree"]["sha"].as_str().ok_or("master commit has no tree")?.to_string();
+
+        if self.config.debug {
+            println!("Using {} at {} as base", base_branch, base_sha);
+        }
+
+        let num_files_to_change = rng.gen_range(self.config.min_files..=self.config.max_files);
+        let existing_files = self.list_change_files(&base_sha).await?;
+        let botignore = self.read_file(BOTIGNORE_FILE, &base_sha).await?;
+        let protected = self.protected_files(botignore.as_deref())?;
+        // A compaction deletes the change files and writes the summary file instead
+        let compacted = self.compaction_due()?;
+        let plan = if compacted {
+            ChangePlan { written: Vec::new(), deleted: self.compactable_files(&protected, existing_files), renamed: None }
+        } else {
+            self.plan_change_files(&protected, existing_files, num_files_to_change, rng)
+        };
+        let mut num_files_to_change = plan.files_changed();
+
+        // One blob per file, all written in a single tree on top of master's
+        let mut tree = Vec::new();
+        for file_name in &plan.deleted {
+            if self.config.debug {
+                println!("Deleting {}/{}", CHANGES_DIR, file_name);
+            }
+            // A null sha removes the path from the base tree
+            tree.push(json!({
+                "path": format!("{}/{}", CHANGES_DIR, file_name),
+                "mode": "100644",
+                "type": "blob",
+                "sha": null,
+            }));
+        }
+        if let Some((from, to)) = &plan.renamed {
+            if self.config.debug {
+                println!("Renaming {}/{} to {}", CHANGES_DIR, from, to);
+            }
+            let from_path = format!("{}/{}", CHANGES_DIR, from);
+            let content = self.read_file(&from_path, &base_sha).await?.ok_or_else(|| format!("{} disappeared", from_path))?;
+            tree.push(json!({ "path": from_path, "mode": "100644", "type": "blob", "sha": null }));
+            tree.push(json!({ "path": format!("{}/{}", CHANGES_DIR, to), "mode": "100644", "type": "blob", "content": content }));
+        }
+        let mut lines_changed = 0;
+        if compacted && !self.is_write_protected(&protected, &format!("{}/{}", CHANGES_DIR, compaction::SUMMARY_FILE)) {
+            let mut lines = 0;
+            for file_name in &plan.deleted {
+                let path = format!("{}/{}", CHANGES_DIR, file_name);
+                lines += self.read_file(&path, &base_sha).await?.map(|content| content.lines().count()).unwrap_or(0);
+            }
+            let summary_path = format!("{}/{}", CHANGES_DIR, compaction::SUMMARY_FILE);
+            let previous = self.read_file(&summary_path, &base_sha).await?.unwrap_or_default();
+            let content = compaction::summary(&previous, Utc::now().date_naive(), plan.deleted.len(), lines);
+            lines_changed += content.lines().count();
+            num_files_to_change += 1;
+            tree.push(json!({ "path": summary_path, "mode": "100644", "type": "blob", "content": content }));
+        }
+        for file_name in &plan.written {
+            let (content, num_lines) = self.generate_content(file_name, rng);
+            if self.config.debug {
+                println!("Writing {} lines to {}/{}", num_lines, CHANGES_DIR, file_name);
+            }
+            let blob: Value = self.api
+                .post(format!("{}/git/blobs", repo_route), &json!({ "content": content, "encoding": "utf-8" }))
+                .await?;
+            tree.push(json!({
+                "path": format!("{}/{}", CHANGES_DIR, file_name),
+                "mode": "100644",
+                "type": "blob",
+                "sha": blob["sha"],
+            }));
+            lines_changed += num_lines;
+        }
+        if self.codeowners_due()? {
+            let path = self.find_codeowners(&base_sha).await?;
+            if !self.is_write_protected(&protected, path) {
+                let content = codeowners::codeowners_content(&self.config.codeowners_users, rng);
+                let blob: Value = self.api
+                    .post(format!("{}/git/blobs", repo_route), &json!({ "content": content, "encoding": "utf-8" }))
+                    .await?;
+                tree.push(json!({ "path": path, "mode": "100644", "type": "blob", "sha": blob["sha"] }));
+            }
+        }
+        if self.config.changelog && !self.is_write_protected(&protected, changelog::CHANGELOG_PATH) {
+            let existing = self.read_file(changelog::CHANGELOG_PATH, &base_sha).await?.unwrap_or_default();
+            let entry = changelog::entry_text(self.config.today(), rng);
+            let blob: Value = self.api
+                .post(
+                    format!("{}/git/blobs", repo_route),
+                    &json!({ "content": changelog::add_entry(&existing, &entry), "encoding": "utf-8" }),
+                )
+                .await?;
+            tree.push(json!({ "path": changelog::CHANGELOG_PATH, "mode": "100644", "type": "blob", "sha": blob["sha"] }));
+        }
+        if self.readme_update_due(rng) && !self.is_write_protected(&protected, readme::README_PATH) {
+            let existing = self.read_file(readme::README_PATH, &base_sha).await?.unwrap_or_default();
+            let content = readme::update_last_updated(&existing, Utc::now().date_naive());
+            let blob: Value = self.api
+                .post(format!("{}/git/blobs", repo_route), &json!({ "content": content, "encoding": "utf-8" }))
+                .await?;
+            tree.push(json!({ "path": readme::README_PATH, "mode": "100644", "type": "blob", "sha": blob["sha"] }));
+        }
+        let new_tree: Value = self.api
+            .post(format!("{}/git/trees", repo_route), &json!({ "base_tree": base_tree, "tree": tree }))
+            .await?;
+
+        let message = if compacted {
+            self.compaction_message(plan.deleted.len())
+        } else {
+            self.commit_message(num_files_to_change, plan.deleted.len(), usize::from(plan.renamed.is_some()), 0, rng)
+        };
+        let mut commit = json!({
+            "message": message,
+            "tree": new_tree["sha"],
+            "parents": [base_sha],
+        });
+        // Without an identity GitHub commits as the token's user at the current time
+        if let Some((name, email)) = self.commit_identity(rng) {
+            let base_date = base_commit["committer"]["date"].as_str()
+                .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+                .map(|date| date.with_timezone(&self.config.timezone()));
+            let date = self.api_commit_time(base_date, rng)?.unwrap_or_else(|| self.config.now());
+            let signature = json!({ "name": name, "email": email, "date": date.to_rfc3339() });
+            commit["author"] = signature.clone();
+            commit["committer"] = signature;
+        }
+        let new_commit: Value = self.api
+            .post(format!("{}/git/commits", repo_route), &commit)
+            .await?;
+        let commit_sha = new_commit["sha"].as_str().ok_or("Created commit has no sha")?;
+
+        let _: Value = self.api
+            .post(
+                format!("{}/git/refs", repo_route),
+                &json!({ "ref": format!("refs/heads/{}", branch_name), "sha": commit_sha }),
+            )
+            .await?;
+
+        if self.config.debug {
+            println!("Created commit {} on branch {}", commit_sha, branch_name);
+        }
+
+        // The Git Data API doesn't count lines, so they come from the commit as GitHub shows it
+        let shown: Result<Value, _> = self.api
+            .get(format!("{}/commits/{}", repo_route, commit_sha))
+            .await;
+        let file_stats = match shown {
+            Ok(commit) => diffstat::from_commit_files(&commit),
+            Err(e) => {
+                eprintln!("Warning: could not count the lines changed on {}: {}", branch_name, e);
+                Vec::new()
+            }
+        };
+
+        Ok(ChangeSet {
+            branch_name,
+            files_changed: num_files_to_change,
+            lines_changed,
+            file_stats,
+            files_pruned: 0,
+            compacted,
+            push_secs: None,
+            humanize_secs: None,
+            base_recovery: None,
+        })
+    }
+
+    /// Names of the files in the changes directory at commit `sha`
+    async fn list_change_files(&self, sha: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let route = format!("/repos/{}/{}/contents/{}?ref={}", self.repo_owner, self.repo_name, CHANGES_DIR, sha);
+        let response = self.api.get_response(route.as_str()).await?;
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            // The first run creates the directory
+            return Ok(Vec::new());
+        }
+        if !status.is_success() {
+            return Err(format!("Failed to list the {} directory: GitHub responded with {}", CHANGES_DIR, status).into());
+        }
+
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let entries: Value = serde_json::from_slice(&body)?;
+        let files = entries.as_array()
+            .ok_or_else(|| format!("{} is not a directory", CHANGES_DIR))?
+            .iter()
+            .filter(|entry| entry["type"] == "file")
+            .filter_map(|entry| entry["name"].as_str().map(String::from))
+            .collect();
+        Ok(files)
+    }
+
+    /// Content of the file at `path` in commit `sha`, or `None` if there is no such file
+    async fn read_file(&self, path: &str, sha: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let route = format!("/repos/{}/{}/contents/{}?ref={}", self.repo_owner, self.repo_name, path, sha);
+        let response = self.api.get_response(route.as_str()).await?;
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(format!("Failed to read {}: GitHub responded with {}", path, status).into());
+        }
+
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let content: Content = serde_json::from_slice(&body)?;
+        Ok(Some(content.decoded_content().ok_or_else(|| format!("{} could not be decoded", path))?))
+    }
+
+    /// Path of the CODEOWNERS file at commit `sha`, or where a new one goes
+    async fn find_codeowners(&self, sha: &str) -> Result<&'static str, Box<dyn std::error::Error>> {
+        for path in codeowners::CODEOWNERS_PATHS {
+            let route = format!("/repos/{}/{}/contents/{}?ref={}", self.repo_owner, self.repo_name, path, sha);
+            if self.api.get_response(route.as_str()).await?.status().is_success() {
+                return Ok(path);
+            }
+        }
+        Ok(codeowners::codeowners_path(|_| false))
+    }
+
+    /// Timestamp for a commit on top of one made at `not_before` when `commit_time_window` is set
+    fn api_commit_time(&self, not_before: Option<DateTime<chrono_tz::Tz>>, rng: &mut StdRng) -> Result<Option<DateTime<chrono_tz::Tz>>, Box<dyn std::error::Error>> {
+        let window = match &self.config.commit_time_window {
+            Some(window) => parse_time_window(window)?,
+            None => return Ok(None),
+        };
+
+        let not_before = not_before.ok_or("master commit has an invalid date")?;
+        let time = pick_commit_time(window, self.config.now(), not_before, rng);
+        if time.is_none() && self.config.debug {
+            println!("No time left in commit_time_window today, using the current time");
+        }
+        Ok(time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client, Config, Engine, GhApi};
+    use std::sync::{Arc, Mutex, OnceLock};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn api_engine_commits_every_file_in_one_tree() {
+        let server = MockServer::start().await;
+        let sha = |n: u32| format!("{:040}", n);
+        let mocks = [
+            ("GET", "/repos/octocat/activity/git/ref/heads/master", 200, json!({ "object": { "sha": sha(1) } })),
+            ("GET", "/repos/octocat/activity/git/commits/0000000000000000000000000000000000000001", 200, json!({
+                "sha": sha(1),
+                "tree": { "sha": sha(2) },
+                "committer": { "date": "2024-05-01T08:00:00Z" }
+            })),
+            ("GET", "/repos/octocat/activity/contents/changes", 200, json!([
+                { "name": "change_1.txt", "type": "file" },
+                { "name": "archive", "type": "dir" }
+            ])),
+            ("POST", "/repos/octocat/activity/git/blobs", 201, json!({ "sha": sha(3) })),
+            ("POST", "/repos/octocat/activity/git/trees", 201, json!({ "sha": sha(4) })),
+            ("POST", "/repos/octocat/activity/git/commits", 201, json!({ "sha": sha(5) })),
+            ("POST", "/repos/octocat/activity/git/refs", 201, json!({ "ref": "refs/heads/bot-update-1" })),
+            ("GET", "/repos/octocat/activity/commits/0000000000000000000000000000000000000005", 200, json!({ "files": [
+                { "filename": "changes/change_2.txt", "status": "added", "additions": 3, "deletions": 0, "changes": 3, "patch": "@@" }
+            ] })),
+        ];
+        for (verb, route, status, body) in mocks {
+            Mock::given(method(verb)).and(path(route))
+                .respond_with(ResponseTemplate::new(status).set_body_json(body))
+                .mount(&server)
+                .await;
+        }
+
+        let mut config: Config = toml::from_str(r#"
+            username = "octocat"
+            repo = "octocat/activity"
+            engine = "api"
+            cron_schedule = "0 0 */2 * * *"
+            min_files = 2
+            max_files = 2
+            min_lines = 1
+            max_lines = 5
+            debug = false
+            author_name = "Octo Cat"
+            author_email = "octocat@example.com"
+        "#).unwrap();
+        config.github_api_url = Some(server.uri());
+        assert_eq!(config.engine, Engine::Api);
+        assert!(config.validate().is_ok());
+        let bot = GitHubBot {
+            api: GhApi::new(client::build_octocrab(&config, "test-token").unwrap()),
+            config,
+            repo_owner: "octocat".to_string(),
+            repo_name: "activity".to_string(),
+            token: "test-token".to_string(),
+            reviewer: None,
+            repo: Arc::new(Mutex::new(None)),
+            run_lock: Arc::new(tokio::sync::Mutex::new(())),
+            last_run_time: Arc::new(Mutex::new(None)),
+            tokens: None,
+            default_branch: Arc::new(OnceLock::new()),
+        };
+
+        bot.default_branch.set("master".to_string()).unwrap();
+        let changes = bot.make_changes_via_api(&mut bot.rng()).await.unwrap();
+
+        assert_eq!(changes.files_changed, 2);
+        assert_eq!(changes.files_pruned, 0);
+        assert_eq!(diffstat::totals(&changes.file_stats), (3, 0));
+        let requests = server.received_requests().await.unwrap();
+        let body = |route: &str| -> Value {
+            let request = requests.iter().find(|request| request.url.path() == route).unwrap();
+            serde_json::from_slice(&request.body).unwrap()
+        };
+        let tree = body("/repos/octocat/activity/git/trees");
+        assert_eq!(tree["base_tree"], sha(2));
+        let mut paths: Vec<&str> = tree["tree"].as_array().unwrap()
+            .iter()
+            .map(|entry| entry["path"].as_str().unwrap())
+            .collect();
+        paths.sort();
+        assert_eq!(paths, vec!["changes/change_1.txt", "changes/change_2.txt"]);
+
+        let commit = body("/repos/octocat/activity/git/commits");
+        assert_eq!(commit["parents"], json!([sha(1)]));
+        assert_eq!(commit["author"]["name"], "Octo Cat");
+        assert_eq!(commit["committer"]["email"], "octocat@example.com");
+
+        let branch = body("/repos/octocat/activity/git/refs");
+        assert_eq!(branch["ref"], format!("refs/heads/{}", changes.branch_name));
+        assert_eq!(branch["sha"], sha(5));
+    }
+}
@@ -0,0 +1,171 @@
+use chrono::NaiveDate;
+use rand::{seq::SliceRandom, Rng};
+
+use crate::messages::MESSAGE_POOL;
+
+/// Path of the changelog, relative to the repository root
+pub const CHANGELOG_PATH: &str = "CHANGELOG.md";
+
+/// A new changelog in the Keep a Changelog format
+const SKELETON: &str = "# Changelog
+
+All notable changes to this project will be documented in this file.
+
+The format is based on [Keep a Changelog](https://keepachangelog.com/en/1.1.0/).
+
+## [Unreleased]
+";
+
+const UNRELEASED_HEADINGS: &[&str] = &["## [Unreleased]", "## Unreleased"];
+
+/// Add `entry` as the first item under the Unreleased heading of `content`, creating the
+/// file's skeleton or the heading if they are missing. Everything else is kept as is.
+pub fn add_entry(content: &str, entry: &str) -> String {
+    let content = if content.trim().is_empty() { SKELETON } else { content };
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+
+    let heading = match find_unreleased(&lines) {
+        Some(heading) => heading,
+        None => {
+            // Above the latest release, or at the end when there is none
+            let at = lines.iter().position(|line| line.starts_with("## ")).unwrap_or(lines.len());
+            let mut section = vec![UNRELEASED_HEADINGS[0].to_string(), String::new()];
+            if at > 0 && !lines[at - 1].trim().is_empty() {
+                section.insert(0, String::new());
+            }
+            lines.splice(at..at, section);
+            find_unreleased(&lines).unwrap_or_default()
+        }
+    };
+
+    let mut at = heading + 1;
+    if lines.get(at).is_some_and(|line| line.trim().is_empty()) {
+        at += 1;
+    } else {
+        lines.insert(at, String::new());
+        at += 1;
+    }
+    // An empty section needs a blank line between the entry and the next heading
+    if lines.get(at).is_some_and(|line| line.starts_with('#')) {
+        lines.insert(at, String::new());
+    }
+    lines.insert(at, format!("- {}", entry));
+
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+    updated
+}
+
+/// Whether `content` has entries under its Unreleased heading and no heading for `version` yet
+pub fn needs_release(content: &str, version: &str) -> bool {
+    let lines: Vec<String> = content.lines().map(String::from).collect();
+    let version_heading = format!("## [{}]", version);
+    if lines.iter().any(|line| line.starts_with(&version_heading)) {
+        return false;
+    }
+    let heading = match find_unreleased(&lines) {
+        Some(heading) => heading,
+        None => return false,
+    };
+    lines[heading + 1..].iter()
+        .take_while(|line| !line.starts_with("## "))
+        .any(|line| line.starts_with("- "))
+}
+
+/// Move the Unreleased entries of `content` under a new `## [version] - date` heading,
+/// leaving the Unreleased section empty, for when a release is tagged
+pub fn release_unreleased(content: &str, version: &str, date: &str) -> String {
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+    if let Some(heading) = find_unreleased(&lines) {
+        lines.splice(heading + 1..heading + 1, [String::new(), format!("## [{}] - {}", version, date)]);
+    }
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+    updated
+}
+
+/// Version of the newest release heading of `content`, e.g. "1.0.0" for `## [1.0.0] - 2024-01-01`
+pub fn latest_release(content: &str) -> Option<&str> {
+    content.lines()
+        .filter(|line| !UNRELEASED_HEADINGS.contains(&line.trim_end()))
+        .find_map(|line| line.strip_prefix("## [")?.split_once(']').map(|(version, _)| version))
+}
+
+/// A dated entry such as "2024-05-01: Add weekly summary", drawn from the message pool
+pub fn entry_text<R: Rng + ?Sized>(date: NaiveDate, rng: &mut R) -> String {
+    let description = MESSAGE_POOL.choose(rng).copied().unwrap_or("update files");
+    let mut chars = description.chars();
+    let first = chars.next().map(|c| c.to_ascii_uppercase()).unwrap_or_default();
+    format!("{}: {}{}", date.format("%Y-%m-%d"), first, chars.as_str())
+}
+
+fn find_unreleased(lines: &[String]) -> Option<usize> {
+    lines.iter().position(|line| UNRELEASED_HEADINGS.contains(&line.trim_end()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Headings separated from other blocks by blank lines, list items right below
+    /// their heading's blank line, no doubled blank lines
+    fn assert_well_formed(content: &str) {
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines[0], "# Changelog", "{}", content);
+        assert!(content.ends_with('\n') && !content.ends_with("\n\n"), "{}", content);
+        for (i, line) in lines.iter().enumerate().skip(1) {
+            if line.starts_with('#') {
+                assert!(lines[i - 1].is_empty(), "no blank line before {:?}:\n{}", line, content);
+            }
+            if line.is_empty() {
+                assert!(!lines[i - 1].is_empty(), "doubled blank line:\n{}", content);
+            }
+            if line.starts_with("- ") {
+                assert!(lines[i - 1].starts_with("- ") || lines[i - 1].is_empty(), "{}", content);
+            }
+        }
+        assert_eq!(lines.iter().filter(|line| UNRELEASED_HEADINGS.contains(line)).count(), 1, "{}", content);
+    }
+
+    #[test]
+    fn entries_stay_well_formed_over_many_runs() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let entry = entry_text(NaiveDate::from_ymd_opt(2024, 5, 1).unwrap(), &mut rng);
+        assert!(entry.starts_with("2024-05-01: ") && entry.chars().nth(12).unwrap().is_uppercase(), "{}", entry);
+
+        let mut content = String::new();
+        for run in 1..=50 {
+            content = add_entry(&content, &format!("2024-05-{:02}: entry {}", run % 28 + 1, run));
+            assert_well_formed(&content);
+            if run % 20 == 0 {
+                assert!(needs_release(&content, &format!("0.{}.0", run / 20)));
+                content = release_unreleased(&content, &format!("0.{}.0", run / 20), "2024-06-01");
+                assert_well_formed(&content);
+            }
+        }
+
+        let lines: Vec<&str> = content.lines().collect();
+        let unreleased = lines.iter().position(|line| *line == "## [Unreleased]").unwrap();
+        assert_eq!(lines[unreleased + 2], "- 2024-05-23: entry 50");
+        assert!(content.contains("## [0.2.0] - 2024-06-01\n\n- 2024-05-13: entry 40"), "{}", content);
+        assert_eq!(content.matches("\n- ").count(), 50);
+        assert!(!needs_release(&content, "0.2.0"));
+        assert!(needs_release(&content, "0.3.0"));
+        assert!(!needs_release(SKELETON, "0.3.0"));
+        assert_eq!(latest_release(&content), Some("0.2.0"));
+        assert_eq!(latest_release(SKELETON), None);
+    }
+
+    #[test]
+    fn existing_content_is_preserved() {
+        let content = "# Changelog\n\nNotes by hand.\n\n## [1.0.0] - 2024-01-01\n\n- First release\n";
+        let updated = add_entry(content, "Add weekly summary");
+
+        assert_eq!(
+            updated,
+            "# Changelog\n\nNotes by hand.\n\n## [Unreleased]\n\n- Add weekly summary\n\n## [1.0.0] - 2024-01-01\n\n- First release\n"
+        );
+        assert_well_formed(&updated);
+    }
+}
@@ -0,0 +1,190 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use crate::state::{ChatOpsCursor, RunRecord, RunStatus};
+use crate::GitHubBot;
+
+/// The `[chatops]` config section
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatOpsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Issue in the target repository whose comments are read as commands
+    pub issue: u64,
+    /// Seconds between polls of the issue's comments
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    /// GitHub users whose commands are obeyed
+    #[serde(default)]
+    pub allowed_users: Vec<String>,
+}
+
+fn default_poll_interval_secs() -> u64 {
+    60
+}
+
+impl ChatOpsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.enabled && self.allowed_users.is_empty() {
+            return Err("chatops.allowed_users must not be empty".to_string());
+        }
+        if self.poll_interval_secs == 0 {
+            return Err("chatops.poll_interval_secs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A command in an issue comment
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// `/bot run`
+    Run,
+    /// `/bot status`
+    Status,
+}
+
+/// The command on the first line of a comment, if any
+pub fn parse_command(body: &str) -> Option<Command> {
+    let mut words = body.lines().next()?.split_whitespace();
+    if words.next()? != "/bot" {
+        return None;
+    }
+    match words.next()? {
+        "run" => Some(Command::Run),
+        "status" => Some(Command::Status),
+        _ => None,
+    }
+}
+
+/// Reply to `/bot status`, describing the last recorded run
+pub fn status_reply(last_run: Option<&RunRecord>) -> String {
+    let run = match last_run {
+        Some(run) => run,
+        None => return "No runs recorded yet.".to_string(),
+    };
+    let status = match run.status {
+        RunStatus::Succeeded => "succeeded",
+        RunStatus::Skipped => "was skipped",
+        RunStatus::Failed => "failed",
+        RunStatus::TimedOut => "timed out",
+    };
+    let mut reply = format!("Last run {} at {}.", status, run.started_at.format("%Y-%m-%d %H:%M UTC"));
+    if let (Some(number), Some(url)) = (run.pr_number, &run.pr_url) {
+        let merged = if run.merged { "merged" } else { "left open" };
+        reply.push_str(&format!(" PR #{} ({}): {}", number, merged, url));
+    }
+    if let Some(error) = &run.error {
+        reply.push_str(&format!("\n\n```\n{}\n```", error));
+    }
+    reply
+}
+
+/// Comments read per request; a shorter page is the last one
+const COMMENTS_PER_PAGE: usize = 100;
+
+fn comment_created_at(comment: &Value) -> Option<DateTime<Utc>> {
+    comment["created_at"].as_str()
+        .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+        .map(|at| at.with_timezone(&Utc))
+}
+
+impl GitHubBot {
+    /// Read the comments on the ChatOps issue since the last poll and carry out the commands
+    /// of allowed users. The first poll only records where to start, so old commands never run.
+    pub(crate) async fn poll_chatops(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let config = match &self.config.chatops {
+            Some(config) if config.enabled => config,
+            _ => return Ok(()),
+        };
+        let issue_route = format!("/repos/{}/{}/issues/{}", self.repo_owner, self.repo_name, config.issue);
+
+        let cursor = self.load_state()?.chatops_cursor;
+        let mut comments: Vec<Value> = Vec::new();
+        for page in 1.. {
+            let mut route = format!("{}/comments?per_page={}&page={}", issue_route, COMMENTS_PER_PAGE, page);
+            if let Some(cursor) = &cursor {
+                route.push_str(&format!("&since={}", cursor.since.format("%Y-%m-%dT%H:%M:%SZ")));
+            }
+            let batch: Vec<Value> = self.api.get(route).await?;
+            let last_page = batch.len() < COMMENTS_PER_PAGE;
+            comments.extend(batch);
+            if last_page {
+                break;
+            }
+        }
+
+        let cursor = match cursor {
+            Some(cursor) => cursor,
+            None => {
+                // Start after the newest comment, or from now on an issue without comments
+                let newest = comments.iter().max_by_key(|comment| comment["id"].as_u64().unwrap_or(0));
+                let start = ChatOpsCursor {
+                    last_comment_id: newest.and_then(|comment| comment["id"].as_u64()).unwrap_or(0),
+                    since: newest.and_then(comment_created_at).unwrap_or_else(Utc::now),
+                };
+                let mut state = self.load_state()?;
+                state.chatops_cursor = Some(start);
+                self.save_state(&state)?;
+                return Ok(());
+            }
+        };
+        let new_comments = comments.iter().filter(|comment| comment["id"].as_u64().unwrap_or(0) > cursor.last_comment_id);
+
+        for comment in new_comments {
+            let id = comment["id"].as_u64().unwrap_or(0);
+            let since = comment_created_at(comment).unwrap_or_else(Utc::now);
+            // Saved before acting, so a restart mid-run doesn't run the command again
+            let mut state = self.load_state()?;
+            state.chatops_cursor = Some(ChatOpsCursor { last_comment_id: id, since });
+            self.save_state(&state)?;
+
+            let command = match comment["body"].as_str().and_then(parse_command) {
+                Some(command) => command,
+                None => continue,
+            };
+            let user = comment["user"]["login"].as_str().unwrap_or_default();
+            if !config.allowed_users.iter().any(|allowed| allowed.eq_ignore_ascii_case(user)) {
+                println!("Ignoring ChatOps command from {}, who is not in chatops.allowed_users", user);
+                continue;
+            }
+            println!("ChatOps: {:?} from {} on issue #{}", command, user, config.issue);
+
+            let reaction_route = format!("/repos/{}/{}/issues/comments/{}/reactions", self.repo_owner, self.repo_name, id);
+            let reacted: Result<Value, _> = self.api.post(reaction_route, &json!({ "content": "eyes" })).await;
+            if let Err(e) = reacted {
+                eprintln!("Warning: could not react to comment {}: {}", id, e);
+            }
+
+            let reply = match command {
+                Command::Status => status_reply(self.load_state()?.run_history.last()),
+                Command::Run => {
+                    let _running = self.run_lock.lock().await;
+                    match self.run_and_record().await {
+                        Ok(report) => format!("@{} run finished.\n\n```\n{}\n```", user, report),
+                        Err(e) => format!("@{} run failed: {}", user, e),
+                    }
+                }
+            };
+            let _: Value = self.api
+                .post(format!("{}/comments", issue_route), &json!({ "body": reply }))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_read_from_the_first_line() {
+        assert_eq!(parse_command("/bot run"), Some(Command::Run));
+        assert_eq!(parse_command("  /bot   status  \nplease"), Some(Command::Status));
+        assert_eq!(parse_command("/bot deploy"), None);
+        assert_eq!(parse_command("please /bot run"), None);
+        assert_eq!(parse_command(""), None);
+    }
+}
@@ -0,0 +1,189 @@
+use git2::ProxyOptions;
+use http::header::{AUTHORIZATION, USER_AGENT};
+use http::{HeaderValue, Uri};
+use hyper::client::connect::Connection;
+use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_timeout::TimeoutConnector;
+use octocrab::service::middleware::base_uri::BaseUriLayer;
+use octocrab::service::middleware::extra_headers::ExtraHeadersLayer;
+use octocrab::service::middleware::retry::RetryConfig;
+use octocrab::{AuthState, Octocrab, OctocrabBuilder};
+use serde::{Serialize, Deserialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower::retry::RetryLayer;
+use tower::timeout::TimeoutLayer;
+use tower::{BoxError, Service};
+
+use crate::Config;
+
+const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// The `[http]` config table: timeouts and retries of every GitHub API request
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HttpConfig {
+    /// Seconds after which a request, including its retries, is abandoned
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Seconds allowed for connecting to the GitHub API
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    /// Seconds an idle connection is kept open for reuse
+    #[serde(default = "default_pool_idle_timeout_secs")]
+    pub pool_idle_timeout_secs: u64,
+    /// Times a failed request is retried
+    #[serde(default = "default_retries")]
+    pub retries: usize,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: default_timeout_secs(),
+            connect_timeout_secs: default_connect_timeout_secs(),
+            pool_idle_timeout_secs: default_pool_idle_timeout_secs(),
+            retries: default_retries(),
+        }
+    }
+}
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    10
+}
+
+fn default_pool_idle_timeout_secs() -> u64 {
+    90
+}
+
+fn default_retries() -> usize {
+    3
+}
+
+impl HttpConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.timeout_secs == 0 || self.connect_timeout_secs == 0 {
+            return Err("http.timeout_secs and http.connect_timeout_secs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Build the GitHub API client, routing requests through the configured proxies and
+/// giving up on requests that take longer than the configured timeouts
+pub fn build_octocrab(config: &Config, token: &str) -> Result<Octocrab, Box<dyn std::error::Error>> {
+    build_octocrab_with_authorization(config, &format!("Bearer {}", token))
+}
+
+/// Like `build_octocrab`, but sending `authorization` as the raw `Authorization` header,
+/// for setups that expect a scheme other than Bearer
+pub fn build_octocrab_with_authorization(config: &Config, authorization: &str) -> Result<Octocrab, Box<dyn std::error::Error>> {
+    let api_url = config.github_api_url.as_deref().unwrap_or(GITHUB_API_URL);
+    let base_uri: Uri = api_url.parse()?;
+    let host = base_uri.host().unwrap_or_default();
+
+    let mut proxies = Vec::new();
+    if !is_no_proxy_host(host, &config.no_proxy) {
+        if let Some(http_proxy) = &config.http_proxy {
+            proxies.push(Proxy::new(Intercept::Http, http_proxy.parse()?));
+        }
+        if let Some(https_proxy) = &config.https_proxy {
+            proxies.push(Proxy::new(Intercept::Https, https_proxy.parse()?));
+        }
+    }
+
+    // Also used under the proxy connector, for the requests no proxy intercepts
+    let https = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    if proxies.is_empty() {
+        return build_with_connector(https, config, base_uri, authorization);
+    }
+
+    if config.debug {
+        println!("Routing GitHub API requests through a proxy");
+    }
+
+    let mut connector = ProxyConnector::new(https)?;
+    for proxy in proxies {
+        connector.add_proxy(proxy);
+    }
+    build_with_connector(connector, config, base_uri, authorization)
+}
+
+/// Put Octocrab's middleware and the configured timeouts on top of a connector
+fn build_with_connector<C>(connector: C, config: &Config, base_uri: Uri, authorization: &str) -> Result<Octocrab, Box<dyn std::error::Error>>
+where
+    C: Service<Uri> + Clone + Send + Sync + 'static,
+    C::Response: AsyncRead + AsyncWrite + Connection + Send + Unpin,
+    C::Future: Send + 'static,
+    C::Error: Into<BoxError>,
+{
+    let mut connector = TimeoutConnector::new(connector);
+    connector.set_connect_timeout(Some(Duration::from_secs(config.http.connect_timeout_secs)));
+    let client = hyper::Client::builder()
+        .pool_idle_timeout(Duration::from_secs(config.http.pool_idle_timeout_secs))
+        .build::<_, String>(connector);
+
+    // Mirror what Octocrab's default builder sets up on top of its own client
+    let headers = vec![
+        (USER_AGENT, HeaderValue::from_static("octocrab")),
+        (AUTHORIZATION, HeaderValue::from_str(authorization)?),
+    ];
+
+    // The timeout covers a request including its retries
+    let octocrab = OctocrabBuilder::new_empty()
+        .with_service(client)
+        .with_layer(&RetryLayer::new(RetryConfig::Simple(config.http.retries)))
+        .with_layer(&TimeoutLayer::new(Duration::from_secs(config.http.timeout_secs)))
+        .with_layer(&BaseUriLayer::new(base_uri))
+        .with_layer(&ExtraHeadersLayer::new(Arc::new(headers)))
+        .with_auth(AuthState::None)
+        .build()?;
+
+    Ok(octocrab)
+}
+
+/// Proxy environment variables for the `git` binary, which honors the same
+/// variables as curl
+pub fn git_proxy_env(config: &Config) -> Vec<(&'static str, String)> {
+    let mut env = Vec::new();
+    if let Some(http_proxy) = &config.http_proxy {
+        env.push(("http_proxy", http_proxy.clone()));
+    }
+    if let Some(https_proxy) = &config.https_proxy {
+        env.push(("https_proxy", https_proxy.clone()));
+    }
+    if !config.no_proxy.is_empty() {
+        env.push(("no_proxy", config.no_proxy.join(",")));
+    }
+    env
+}
+
+/// Proxy settings for libgit2 transfers, which don't read the proxy variables
+pub fn git2_proxy_options(config: &Config) -> Option<ProxyOptions<'static>> {
+    let proxy = config.https_proxy.as_ref().or(config.http_proxy.as_ref())?;
+    if is_no_proxy_host("github.com", &config.no_proxy) {
+        return None;
+    }
+
+    let mut options = ProxyOptions::new();
+    options.url(proxy);
+    Some(options)
+}
+
+/// Whether `host` matches an entry of a curl-style `no_proxy` list
+fn is_no_proxy_host(host: &str, no_proxy: &[String]) -> bool {
+    no_proxy.iter().any(|entry| {
+        let entry = entry.trim().trim_start_matches('.');
+        entry == "*"
+            || host.eq_ignore_ascii_case(entry)
+            || host.to_ascii_lowercase().ends_with(&format!(".{}", entry.to_ascii_lowercase()))
+    })
+}
@@ -0,0 +1,49 @@
+use rand::{seq::SliceRandom, Rng};
+
+/// Where GitHub looks for a CODEOWNERS file, in the order it checks them
+pub const CODEOWNERS_PATHS: &[&str] = &[".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+/// The CODEOWNERS file to rewrite: the first one that exists, or `.github/CODEOWNERS`
+pub fn codeowners_path(exists: impl Fn(&str) -> bool) -> &'static str {
+    CODEOWNERS_PATHS
+        .iter()
+        .copied()
+        .find(|path| exists(path))
+        .unwrap_or(CODEOWNERS_PATHS[0])
+}
+
+/// A CODEOWNERS file making a random, non-empty subset of `users` the owners of everything
+pub fn codeowners_content<R: Rng + ?Sized>(users: &[String], rng: &mut R) -> String {
+    let count = rng.gen_range(1..=users.len().max(1));
+    let mut owners: Vec<String> = users
+        .choose_multiple(rng, count)
+        .map(|user| format!("@{}", user.trim_start_matches('@')))
+        .collect();
+    owners.sort();
+    format!("# Maintainers of this repository\n* {}\n", owners.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn codeowners_lists_a_subset_of_the_users() {
+        let users = vec!["octocat".to_string(), "@hubot".to_string(), "monalisa".to_string()];
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..20 {
+            let content = codeowners_content(&users, &mut rng);
+            let rule = content.lines().find(|line| line.starts_with("* ")).unwrap();
+            let owners: Vec<&str> = rule.split_whitespace().skip(1).collect();
+            assert!(!owners.is_empty(), "{}", content);
+            assert!(owners.iter().all(|owner| ["@octocat", "@hubot", "@monalisa"].contains(owner)), "{}", content);
+        }
+    }
+
+    #[test]
+    fn existing_codeowners_file_is_reused() {
+        assert_eq!(codeowners_path(|_| false), ".github/CODEOWNERS");
+        assert_eq!(codeowners_path(|path| path == "docs/CODEOWNERS"), "docs/CODEOWNERS");
+    }
+}
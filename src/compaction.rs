@@ -0,0 +1,195 @@
+use chrono::{NaiveDate, Utc};
+use git2::{Oid, PushOptions};
+use serde::{Serialize, Deserialize};
+use std::{collections::HashSet, fs, path::Path};
+
+use crate::messages::{self, CommitStyle};
+use crate::{client, ChangeFiles, GitHubBot, ProtectedFiles, CHANGES_DIR};
+
+/// File in the changes directory that replaces the compacted change files
+pub const SUMMARY_FILE: &str = "SUMMARY.md";
+
+const SUMMARY_HEADER: &str = "# Change File Summary\n\nOld bot change files are compacted into this file to keep the repository small.\n\n| Compacted | Files | Lines |\n| --- | ---: | ---: |\n";
+
+/// The `[compaction]` config section
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CompactionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Compact on every Nth run
+    #[serde(default)]
+    pub every_n_runs: Option<u32>,
+    /// Cron schedule on which the next run compacts
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// After the compaction PR is merged, replace the default branch's history with a single
+    /// snapshot commit and force-push it. Destroys history; needs `--yes-i-know`.
+    #[serde(default)]
+    pub rewrite_history: bool,
+}
+
+impl CompactionConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.enabled && self.every_n_runs.is_none() && self.schedule.is_none() {
+            return Err("compaction needs every_n_runs or schedule".to_string());
+        }
+        if self.every_n_runs == Some(0) {
+            return Err("compaction.every_n_runs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The summary file after compacting `files` files with `lines` lines on `date`, keeping the
+/// rows of earlier compactions from `previous`
+pub fn summary(previous: &str, date: NaiveDate, files: usize, lines: usize) -> String {
+    let mut content = SUMMARY_HEADER.to_string();
+    for row in previous.lines().filter(|line| line.starts_with("| ") && line.chars().nth(2).is_some_and(|c| c.is_ascii_digit())) {
+        content.push_str(row);
+        content.push('\n');
+    }
+    content.push_str(&format!("| {} | {} | {} |\n", date, files, lines));
+    content
+}
+
+impl GitHubBot {
+    /// Count this run towards `compaction.every_n_runs`, returning whether it compacts the
+    /// changes directory, either because enough runs passed or the schedule asked for it
+    pub(crate) fn compaction_due(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        let compaction = match &self.config.compaction {
+            Some(compaction) if compaction.enabled => compaction,
+            _ => return Ok(false),
+        };
+        let mut state = self.load_state()?;
+        state.runs_since_compaction += 1;
+        let due = state.compaction_requested
+            || compaction.every_n_runs.is_some_and(|runs| state.runs_since_compaction >= runs);
+        if due {
+            state.runs_since_compaction = 0;
+            state.compaction_requested = false;
+        }
+        self.save_state(&state)?;
+        Ok(due)
+    }
+
+    /// Make the next run compact the changes directory
+    pub(crate) fn request_compaction(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut state = self.load_state()?;
+        state.compaction_requested = true;
+        self.save_state(&state)
+    }
+
+    /// Names of the change files a compaction removes: all but the summary and `protected` files
+    pub(crate) fn compactable_files(&self, protected: &ProtectedFiles, existing_files: Vec<String>) -> Vec<String> {
+        let mut files: Vec<String> = existing_files
+            .into_iter()
+            .filter(|file_name| file_name != SUMMARY_FILE)
+            .filter(|file_name| !protected.covers(&format!("{}/{}", CHANGES_DIR, file_name)))
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Replace the change files in `changes_dir` with a row in the summary file
+    pub(crate) fn compact_change_files(&self, protected: &ProtectedFiles, changes_dir: &Path) -> Result<ChangeFiles, Box<dyn std::error::Error>> {
+        let existing_files = fs::read_dir(changes_dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| entry.file_name().to_str().map(String::from))
+            .collect();
+        let files = self.compactable_files(protected, existing_files);
+
+        let mut lines = 0;
+        for file_name in &files {
+            let path = changes_dir.join(file_name);
+            lines += fs::read_to_string(&path).map(|content| content.lines().count()).unwrap_or(0);
+            fs::remove_file(&path)?;
+        }
+        // A protected summary file is left as is, so the compaction only deletes
+        let mut written = HashSet::new();
+        let mut lines_changed = 0;
+        if !self.is_write_protected(protected, &format!("{}/{}", CHANGES_DIR, SUMMARY_FILE)) {
+            let summary_path = changes_dir.join(SUMMARY_FILE);
+            let previous = fs::read_to_string(&summary_path).unwrap_or_default();
+            let content = summary(&previous, Utc::now().date_naive(), files.len(), lines);
+            fs::write(&summary_path, &content)?;
+            lines_changed = content.lines().count();
+            written.insert(SUMMARY_FILE.to_string());
+        }
+        println!("Compacted {} change files into {}/{}", files.len(), CHANGES_DIR, SUMMARY_FILE);
+
+        Ok(ChangeFiles {
+            files_changed: files.len() + written.len(),
+            lines_changed,
+            written,
+            files_deleted: files.len(),
+            files_renamed: 0,
+        })
+    }
+
+    /// Commit message of a compaction that removed `files_compacted` change files
+    pub(crate) fn compaction_message(&self, files_compacted: usize) -> String {
+        match self.config.commit_style {
+            CommitStyle::Plain => format!(
+                "Update {} files in changes directory, compacted {} into {}",
+                files_compacted + 1, files_compacted, SUMMARY_FILE,
+            ),
+            CommitStyle::Conventional => format!("chore({}): {}", CHANGES_DIR, messages::COMPACTION_DESCRIPTION),
+        }
+    }
+
+    /// Replace the default branch with a parentless commit of its current tree and force-push it,
+    /// discarding all earlier history on GitHub
+    pub(crate) async fn snapshot_history(&self) -> Result<Oid, Box<dyn std::error::Error>> {
+        let base = self.default_branch()?;
+        self.checkout_branch(base)?;
+        self.pull_branch(base).await?;
+
+        let oid = {
+            let repo = self.repository()?;
+            let head = repo.head()?.peel_to_commit()?;
+            let signature = match self.commit_identity(&mut self.rng()) {
+                Some((name, email)) => git2::Signature::now(name, email)?,
+                None => repo.signature()?,
+            };
+            let message = format!("Snapshot of {} as of {}", self.config.repo, Utc::now().format("%Y-%m-%d"));
+            let oid = repo.commit(None, &signature, &signature, &message, &head.tree()?, &[])?;
+            repo.find_reference(&format!("refs/heads/{}", base))?
+                .set_target(oid, "compaction: snapshot history")?;
+            oid
+        };
+
+        if self.config.secure_auth {
+            let repo = self.repository()?;
+            let mut remote = repo.find_remote(&self.config.remote)?;
+            let mut push_options = PushOptions::new();
+            push_options.remote_callbacks(self.remote_callbacks());
+            if let Some(proxy_options) = client::git2_proxy_options(&self.config) {
+                push_options.proxy_options(proxy_options);
+            }
+            let refspec = format!("+refs/heads/{}:refs/heads/{}", base, base);
+            remote.push(&[refspec.as_str()], Some(&mut push_options))
+                .map_err(|e| format!("Failed to force-push {}: {}", base, e.message()))?;
+        } else {
+            self.run_git_push(&["push", "--force", &self.config.remote, base]).await?;
+        }
+        println!("Replaced the history of {} with snapshot {}", base, oid);
+        Ok(oid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summaries_keep_earlier_compactions() {
+        let day = |day| NaiveDate::from_ymd_opt(2024, 5, day).unwrap();
+        let first = summary("", day(1), 120, 3400);
+        assert!(first.starts_with("# Change File Summary"), "{}", first);
+        let second = summary(&first, day(8), 30, 700);
+        let rows: Vec<&str> = second.lines().filter(|line| line.starts_with("| 2024")).collect();
+        assert_eq!(rows, vec!["| 2024-05-01 | 120 | 3400 |", "| 2024-05-08 | 30 | 700 |"]);
+        assert_eq!(second.matches("| Compacted |").count(), 1);
+    }
+}
@@ -0,0 +1,331 @@
+use chrono::Utc;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// One of the `[[file_types]]`: an extension for new change files and the size of its files
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileTypeConfig {
+    /// Extension without the dot, e.g. "md"
+    pub extension: String,
+    /// Relative chance of a new file getting this extension
+    pub weight: f64,
+    pub min_lines: usize,
+    pub max_lines: usize,
+}
+
+pub fn validate_file_types(file_types: &[FileTypeConfig]) -> Result<(), String> {
+    if file_types.is_empty() {
+        return Err("file_types needs at least one entry".to_string());
+    }
+    for file_type in file_types {
+        let extension = &file_type.extension;
+        if extension.is_empty() || !extension.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("file_types extension '{}' must be letters and digits only", extension));
+        }
+        if !file_type.weight.is_finite() || file_type.weight <= 0.0 {
+            return Err(format!("file_types weight for {} must be greater than 0", extension));
+        }
+        if file_type.min_lines == 0 || file_type.min_lines > file_type.max_lines {
+            return Err(format!("file_types lines for {} must satisfy 1 <= min_lines <= max_lines", extension));
+        }
+    }
+    Ok(())
+}
+
+/// Extension of a new change file: drawn from `file_types` by weight, or "txt" without
+/// drawing from `rng` when there are none
+pub fn choose_extension<'a, R: Rng + ?Sized>(file_types: Option<&'a [FileTypeConfig]>, rng: &mut R) -> &'a str {
+    match file_types {
+        Some(file_types) => file_types
+            .choose_weighted(rng, |file_type| file_type.weight)
+            .map(|file_type| file_type.extension.as_str())
+            .unwrap_or("txt"),
+        None => "txt",
+    }
+}
+
+/// The file type configured for a file's extension
+pub fn file_type_for<'a>(file_types: Option<&'a [FileTypeConfig]>, file_name: &str) -> Option<&'a FileTypeConfig> {
+    let extension = Path::new(file_name).extension()?.to_string_lossy().to_lowercase();
+    file_types?.iter().find(|file_type| file_type.extension.eq_ignore_ascii_case(&extension))
+}
+
+/// Generates the content of a change file
+pub trait ContentStrategy {
+    /// Content of roughly `num_lines` lines
+    fn generate(&self, num_lines: usize, rng: &mut StdRng) -> String;
+}
+
+/// Pick the content strategy for a file from its extension
+pub fn strategy_for(file_name: &str, seeded: bool) -> Box<dyn ContentStrategy> {
+    let extension = Path::new(file_name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("md") => Box::new(MarkdownStrategy),
+        Some("json") => Box::new(JsonStrategy),
+        Some("rs") => Box::new(RustStrategy),
+        _ => Box::new(TimestampedStrategy { seeded }),
+    }
+}
+
+/// "Line {n}: Bot update at {timestamp}" lines. Seeded runs must not depend on the
+/// clock, so they stamp lines with a random id instead.
+pub struct TimestampedStrategy {
+    pub seeded: bool,
+}
+
+impl ContentStrategy for TimestampedStrategy {
+    fn generate(&self, num_lines: usize, rng: &mut StdRng) -> String {
+        let mut content = String::new();
+        for i in 0..num_lines {
+            let stamp = if self.seeded {
+                format!("{:016x}", rng.gen::<u64>())
+            } else {
+                Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
+            };
+            content.push_str(&format!("Line {}: Bot update at {}\n", i + 1, stamp));
+        }
+        content
+    }
+}
+
+/// A Markdown document with a heading, paragraphs, a fenced code block and a table,
+/// padded with further sections up to the requested number of lines
+pub struct MarkdownStrategy;
+
+const HEADINGS: &[&str] = &[
+    "Weekly Notes",
+    "Project Log",
+    "Release Checklist",
+    "Design Sketch",
+    "Working Notes",
+    "Setup Guide",
+    "Troubleshooting",
+    "Meeting Summary",
+];
+
+const SECTION_HEADINGS: &[&str] = &["Background", "Details", "Next Steps", "Open Questions", "Notes", "Follow-up"];
+
+const LOREM: &[&str] = &[
+    "lorem", "ipsum", "dolor", "sit", "amet", "consectetur", "adipiscing", "elit", "sed", "do",
+    "eiusmod", "tempor", "incididunt", "ut", "labore", "et", "dolore", "magna", "aliqua", "enim",
+    "ad", "minim", "veniam", "quis", "nostrud", "exercitation", "ullamco", "laboris", "nisi",
+];
+
+const TABLE_ITEMS: &[&str] = &["parser", "scheduler", "cache", "exporter", "client", "docs", "tests", "config"];
+
+const STATUSES: &[&str] = &["done", "in progress", "planned", "blocked"];
+
+impl ContentStrategy for MarkdownStrategy {
+    fn generate(&self, num_lines: usize, rng: &mut StdRng) -> String {
+        let mut lines = vec![format!("# {}", HEADINGS.choose(rng).unwrap()), String::new()];
+        for _ in 0..rng.gen_range(1..=2) {
+            lines.push(paragraph(rng));
+            lines.push(String::new());
+        }
+        lines.extend(code_block(rng));
+        lines.push(String::new());
+        lines.extend(table(rng));
+
+        while lines.len() < num_lines {
+            lines.push(String::new());
+            lines.push(format!("## {}", SECTION_HEADINGS.choose(rng).unwrap()));
+            lines.push(String::new());
+            lines.push(paragraph(rng));
+        }
+
+        let mut content = lines.join("\n");
+        content.push('\n');
+        content
+    }
+}
+
+/// A paragraph of two to four Lorem-ipsum sentences on a single line
+fn paragraph(rng: &mut StdRng) -> String {
+    let sentences: Vec<String> = (0..rng.gen_range(2..=4))
+        .map(|_| {
+            let words: Vec<&str> = (0..rng.gen_range(6..=12)).map(|_| *LOREM.choose(rng).unwrap()).collect();
+            let sentence = words.join(" ");
+            let mut chars = sentence.chars();
+            let first = chars.next().map(|c| c.to_ascii_uppercase()).unwrap_or_default();
+            format!("{}{}.", first, chars.as_str())
+        })
+        .collect();
+    sentences.join(" ")
+}
+
+/// A fenced code block with a small, plausible snippet in a random language
+fn code_block(rng: &mut StdRng) -> Vec<String> {
+    let item = *TABLE_ITEMS.choose(rng).unwrap();
+    let count = rng.gen_range(2..=9);
+    let (language, body) = match rng.gen_range(0..4) {
+        0 => ("rust", format!(
+            "fn {item}_count() -> usize {{\n    let items = vec![{values}];\n    items.len()\n}}",
+            values = (1..=count).map(|n| n.to_string()).collect::<Vec<_>>().join(", "),
+        )),
+        1 => ("bash", format!(
+            "#!/usr/bin/env bash\nset -euo pipefail\nfor i in $(seq 1 {count}); do\n    echo \"checking {item} $i\"\ndone",
+        )),
+        2 => ("python", format!(
+            "def {item}_summary(items):\n    return {{name: len(name) for name in items[:{count}]}}",
+        )),
+        _ => ("json", serde_json::to_string_pretty(&json!({
+            "name": item,
+            "enabled": rng.gen_bool(0.5),
+            "retries": count,
+            "tags": [LOREM.choose(rng).unwrap(), LOREM.choose(rng).unwrap()],
+        })).unwrap()),
+    };
+
+    let mut lines = vec![format!("```{}", language)];
+    lines.extend(body.lines().map(String::from));
+    lines.push("```".to_string());
+    lines
+}
+
+/// A pretty-printed JSON document whose `items` array grows to the requested number of lines
+pub struct JsonStrategy;
+
+/// Lines of a pretty-printed `items` entry
+const JSON_ITEM_LINES: usize = 6;
+
+impl ContentStrategy for JsonStrategy {
+    fn generate(&self, num_lines: usize, rng: &mut StdRng) -> String {
+        // `{`, "name", "version", "items": [, `]` and `}`
+        let item_count = num_lines.saturating_sub(6).div_ceil(JSON_ITEM_LINES).max(1);
+        let items: Vec<Value> = (0..item_count)
+            .map(|id| json!({
+                "id": id + 1,
+                "name": TABLE_ITEMS.choose(rng).unwrap(),
+                "status": STATUSES.choose(rng).unwrap(),
+                "count": rng.gen_range(1..=50),
+            }))
+            .collect();
+        let document = json!({
+            "name": HEADINGS.choose(rng).unwrap(),
+            "version": format!("0.{}.{}", rng.gen_range(1..=9), rng.gen_range(0..=20)),
+            "items": items,
+        });
+        let mut content = serde_json::to_string_pretty(&document).unwrap();
+        content.push('\n');
+        content
+    }
+}
+
+/// A Rust module of small documented functions, added until the requested number of lines
+pub struct RustStrategy;
+
+impl ContentStrategy for RustStrategy {
+    fn generate(&self, num_lines: usize, rng: &mut StdRng) -> String {
+        let mut lines = vec![format!("//! {}", HEADINGS.choose(rng).unwrap())];
+        let mut index = 0;
+        while index == 0 || lines.len() < num_lines {
+            index += 1;
+            let item = *TABLE_ITEMS.choose(rng).unwrap();
+            let values: Vec<String> = (0..rng.gen_range(2..=6)).map(|_| rng.gen_range(1..=99).to_string()).collect();
+            lines.push(String::new());
+            lines.push(format!("/// {}", paragraph(rng)));
+            lines.push(format!("pub fn {}_total_{}() -> u32 {{", item, index));
+            lines.push(format!("    let values = [{}];", values.join(", ")));
+            lines.push("    values.iter().sum()".to_string());
+            lines.push("}".to_string());
+        }
+
+        let mut content = lines.join("\n");
+        content.push('\n');
+        content
+    }
+}
+
+/// A small table of components and their status
+fn table(rng: &mut StdRng) -> Vec<String> {
+    let mut lines = vec!["| Item | Status | Count |".to_string(), "| --- | --- | ---: |".to_string()];
+    let rows = rng.gen_range(2..=4);
+    let items: Vec<&&str> = TABLE_ITEMS.choose_multiple(rng, rows).collect();
+    for item in items {
+        lines.push(format!("| {} | {} | {} |", item, STATUSES.choose(rng).unwrap(), rng.gen_range(1..=50)));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn markdown_has_heading_code_block_and_table() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for num_lines in [1, 40] {
+            let content = strategy_for("notes.md", true).generate(num_lines, &mut rng);
+            let lines: Vec<&str> = content.lines().collect();
+
+            assert!(lines[0].starts_with("# "), "{}", content);
+            assert!(lines.len() >= num_lines, "{}", content);
+            let fence = lines.iter().position(|line| line.starts_with("```") && line.len() > 3).unwrap();
+            let language = &lines[fence][3..];
+            assert!(["rust", "bash", "python", "json"].contains(&language), "{}", content);
+            let close = fence + 1 + lines[fence + 1..].iter().position(|line| *line == "```").unwrap();
+            if language == "json" {
+                let snippet = lines[fence + 1..close].join("\n");
+                assert!(serde_json::from_str::<serde_json::Value>(&snippet).is_ok(), "{}", snippet);
+            }
+            assert!(lines.contains(&"| Item | Status | Count |"), "{}", content);
+        }
+    }
+
+    #[test]
+    fn json_and_rust_files_parse() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for num_lines in [1, 10, 200] {
+            let content = strategy_for("change_1.json", true).generate(num_lines, &mut rng);
+            assert!(serde_json::from_str::<serde_json::Value>(&content).is_ok(), "{}", content);
+            assert!(content.lines().count() >= num_lines, "{}", content);
+
+            let content = strategy_for("change_2.rs", true).generate(num_lines, &mut rng);
+            assert!(syn::parse_file(&content).is_ok(), "{}", content);
+            assert!(content.lines().count() >= num_lines, "{}", content);
+        }
+    }
+
+    #[test]
+    fn extensions_are_drawn_by_weight() {
+        let file_types: Vec<FileTypeConfig> = toml::from_str::<toml::Value>(r#"
+            [[file_types]]
+            extension = "md"
+            weight = 3.0
+            min_lines = 5
+            max_lines = 50
+
+            [[file_types]]
+            extension = "json"
+            weight = 1.0
+            min_lines = 10
+            max_lines = 200
+        "#).unwrap()["file_types"].clone().try_into().unwrap();
+        validate_file_types(&file_types).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(choose_extension(None, &mut rng), "txt");
+        let markdown = (0..400).filter(|_| choose_extension(Some(&file_types), &mut rng) == "md").count();
+        assert!((250..350).contains(&markdown), "md drawn {} times", markdown);
+        assert_eq!(file_type_for(Some(&file_types), "change_3.JSON").unwrap().max_lines, 200);
+        assert!(file_type_for(Some(&file_types), "change_3.txt").is_none());
+
+        assert!(validate_file_types(&[]).is_err());
+        let mut zero_weight = file_types.clone();
+        zero_weight[0].weight = 0.0;
+        assert!(validate_file_types(&zero_weight).unwrap_err().contains("weight"));
+    }
+
+    #[test]
+    fn other_extensions_get_timestamped_lines() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let content = strategy_for("change_1.txt", true).generate(3, &mut rng);
+        assert_eq!(content.lines().count(), 3);
+        assert!(content.starts_with("Line 1: Bot update at "), "{}", content);
+    }
+}
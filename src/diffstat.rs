@@ -0,0 +1,151 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+/// Lines added and removed in one file of a commit
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FileStat {
+    pub path: String,
+    /// Previous path of a renamed file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed_from: Option<String>,
+    /// `None` for binary files, which have no line counts
+    pub additions: Option<usize>,
+    pub deletions: Option<usize>,
+}
+
+impl FileStat {
+    pub fn is_binary(&self) -> bool {
+        self.additions.is_none() && self.deletions.is_none()
+    }
+
+    /// The file's name in a summary, e.g. "changes/old.txt → changes/new.txt"
+    pub fn display_path(&self) -> String {
+        match &self.renamed_from {
+            Some(from) => format!("{} → {}", from, self.path),
+            None => self.path.clone(),
+        }
+    }
+}
+
+/// Total lines added and removed over `stats`, leaving out binary files
+pub fn totals(stats: &[FileStat]) -> (usize, usize) {
+    stats.iter().fold((0, 0), |(added, deleted), stat| {
+        (added + stat.additions.unwrap_or(0), deleted + stat.deletions.unwrap_or(0))
+    })
+}
+
+/// Parse the output of `git diff --numstat -z -M`. Binary files show `-` for both counts,
+/// and renames leave the path empty and follow it with the old and new path.
+pub fn parse_numstat(output: &str) -> Result<Vec<FileStat>, String> {
+    let mut fields = output.split('\0');
+    let mut stats = Vec::new();
+    while let Some(entry) = fields.next() {
+        let entry = entry.trim_start_matches('\n');
+        if entry.is_empty() {
+            continue;
+        }
+        let mut parts = entry.splitn(3, '\t');
+        let (additions, deletions, path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(additions), Some(deletions), Some(path)) => (additions, deletions, path),
+            _ => return Err(format!("Unexpected numstat line: {:?}", entry)),
+        };
+        let (path, renamed_from) = if path.is_empty() {
+            let from = fields.next().ok_or("Numstat rename is missing its old path")?;
+            let to = fields.next().ok_or("Numstat rename is missing its new path")?;
+            (to.to_string(), Some(from.to_string()))
+        } else {
+            (path.to_string(), None)
+        };
+        stats.push(FileStat {
+            path,
+            renamed_from,
+            additions: parse_count(additions)?,
+            deletions: parse_count(deletions)?,
+        });
+    }
+    Ok(stats)
+}
+
+fn parse_count(count: &str) -> Result<Option<usize>, String> {
+    if count == "-" {
+        return Ok(None);
+    }
+    count.parse().map(Some).map_err(|_| format!("Unexpected numstat count: {:?}", count))
+}
+
+/// Stats from the `files` of a commit fetched from the GitHub API. Files GitHub shows no
+/// patch for count as binary.
+pub fn from_commit_files(commit: &Value) -> Vec<FileStat> {
+    let files = commit["files"].as_array().map(Vec::as_slice).unwrap_or_default();
+    files
+        .iter()
+        .filter_map(|file| {
+            let path = file["filename"].as_str()?.to_string();
+            let renamed = file["status"] == "renamed";
+            let renamed_from = file["previous_filename"].as_str().filter(|_| renamed).map(str::to_string);
+            let counts = |key: &str| file[key].as_u64().map(|count| count as usize);
+            let binary = !renamed && file["patch"].is_null() && counts("changes").unwrap_or(0) == 0;
+            Some(FileStat {
+                path,
+                renamed_from,
+                additions: if binary { None } else { counts("additions") },
+                deletions: if binary { None } else { counts("deletions") },
+            })
+        })
+        .collect()
+}
+
+/// Markdown table of the files a PR changes, for its body
+pub fn summary_table(stats: &[FileStat]) -> String {
+    let mut table = String::from("| File | Added | Removed |\n| --- | ---: | ---: |\n");
+    for stat in stats {
+        let count = |count: Option<usize>| count.map(|count| count.to_string()).unwrap_or_else(|| "binary".to_string());
+        table.push_str(&format!("| `{}` | {} | {} |\n", stat.display_path(), count(stat.additions), count(stat.deletions)));
+    }
+    let (added, deleted) = totals(stats);
+    table.push_str(&format!("| **Total** | **{}** | **{}** |\n", added, deleted));
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn numstat_handles_binary_files_and_renames() {
+        let output = "3\t1\tchanges/a.txt\0-\t-\tlogo.png\00\t0\t\0changes/old.txt\0changes/brisk-harbor.txt\0";
+
+        let stats = parse_numstat(output).unwrap();
+
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0], FileStat { path: "changes/a.txt".to_string(), renamed_from: None, additions: Some(3), deletions: Some(1) });
+        assert!(stats[1].is_binary());
+        assert_eq!(stats[2].path, "changes/brisk-harbor.txt");
+        assert_eq!(stats[2].renamed_from.as_deref(), Some("changes/old.txt"));
+        assert_eq!(totals(&stats), (3, 1));
+        assert!(parse_numstat("").unwrap().is_empty());
+        assert!(parse_numstat("x\t1\tfile\0").is_err());
+
+        let table = summary_table(&stats);
+        assert!(table.contains("| `logo.png` | binary | binary |"), "{}", table);
+        assert!(table.contains("`changes/old.txt → changes/brisk-harbor.txt`"), "{}", table);
+        assert!(table.contains("| **Total** | **3** | **1** |"), "{}", table);
+    }
+
+    #[test]
+    fn commit_files_from_the_api_become_stats() {
+        let commit = json!({ "files": [
+            { "filename": "changes/a.txt", "status": "modified", "additions": 4, "deletions": 2, "changes": 6, "patch": "@@" },
+            { "filename": "changes/b.txt", "previous_filename": "changes/old.txt", "status": "renamed", "additions": 0, "deletions": 0, "changes": 0 },
+            { "filename": "logo.png", "status": "added", "additions": 0, "deletions": 0, "changes": 0 },
+        ]});
+
+        let stats = from_commit_files(&commit);
+
+        assert_eq!(stats[0].additions, Some(4));
+        assert_eq!(stats[1].renamed_from.as_deref(), Some("changes/old.txt"));
+        assert_eq!(stats[1].additions, Some(0));
+        assert!(stats[2].is_binary());
+    }
+}
@@ -0,0 +1,151 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::notify::Channel;
+use crate::state::{RunRecord, RunStatus};
+
+/// Number of days a digest covers
+pub const DIGEST_DAYS: i64 = 7;
+
+/// The `[digest]` config section
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DigestConfig {
+    /// Cron expression for sending the digest, e.g. "0 0 9 * * Mon"
+    pub schedule: String,
+    /// Where the digest is sent
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+}
+
+/// Summarize the runs of the `DIGEST_DAYS` days before `now`
+pub fn format_digest(repo: &str, history: &[RunRecord], now: DateTime<Utc>) -> String {
+    let since = now - Duration::days(DIGEST_DAYS);
+    let mut runs: Vec<&RunRecord> = history
+        .iter()
+        .filter(|run| run.started_at > since && run.started_at <= now)
+        .collect();
+    runs.sort_by_key(|run| run.started_at);
+
+    let count = |status: RunStatus| runs.iter().filter(|run| run.status == status).count();
+    let merged: Vec<&&RunRecord> = runs.iter().filter(|run| run.merged).collect();
+    let files: usize = runs.iter().map(|run| run.files_changed).sum();
+    let lines: usize = runs.iter().map(|run| run.lines_changed).sum();
+
+    let mut digest = format!(
+        "Activity digest for {}, {} to {}\n",
+        repo,
+        since.format("%Y-%m-%d"),
+        now.format("%Y-%m-%d")
+    );
+    digest.push_str(&format!(
+        "Runs: {} attempted, {} succeeded, {} skipped, {} failed\n",
+        runs.len(),
+        count(RunStatus::Succeeded),
+        count(RunStatus::Skipped),
+        count(RunStatus::Failed) + count(RunStatus::TimedOut)
+    ));
+
+    digest.push_str(&format!("PRs merged: {}\n", merged.len()));
+    for run in &merged {
+        match (run.pr_number, &run.pr_url) {
+            (Some(number), Some(url)) => digest.push_str(&format!("  - #{} {}\n", number, url)),
+            (Some(number), None) => digest.push_str(&format!("  - #{}\n", number)),
+            _ => {}
+        }
+    }
+
+    digest.push_str(&format!("Changes: {} files, {} lines\n", files, lines));
+
+    let longest_gap = runs
+        .windows(2)
+        .map(|pair| pair[1].started_at - pair[0].started_at)
+        .max();
+    match longest_gap {
+        Some(gap) => digest.push_str(&format!("Longest gap between runs: {}\n", format_duration(gap))),
+        None => digest.push_str("Longest gap between runs: n/a\n"),
+    }
+
+    let idle_days: Vec<String> = (0..DIGEST_DAYS)
+        .rev()
+        .map(|days_ago| (now - Duration::days(days_ago)).date_naive())
+        .filter(|day| !had_activity(&runs, *day))
+        .map(|day| day.format("%Y-%m-%d").to_string())
+        .collect();
+    if idle_days.is_empty() {
+        digest.push_str("Days without activity: none");
+    } else {
+        digest.push_str(&format!("Days without activity: {}", idle_days.join(", ")));
+    }
+
+    digest
+}
+
+/// Whether a run on `day` succeeded and changed files
+fn had_activity(runs: &[&RunRecord], day: NaiveDate) -> bool {
+    runs.iter().any(|run| {
+        run.started_at.date_naive() == day && run.status == RunStatus::Succeeded && run.files_changed > 0
+    })
+}
+
+/// Format a duration as e.g. "1d 4h 5m"
+fn format_duration(duration: Duration) -> String {
+    let days = duration.num_days();
+    let hours = duration.num_hours() % 24;
+    let minutes = duration.num_minutes() % 60;
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else {
+        format!("{}h {}m", hours, minutes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::RunType;
+    use chrono::TimeZone;
+
+    fn run(started_at: DateTime<Utc>, status: RunStatus, pr_number: Option<u64>) -> RunRecord {
+        RunRecord {
+            run_id: None,
+            run_type: RunType::Pr,
+            started_at,
+            status,
+            pr_number,
+            pr_url: pr_number.map(|number| format!("https://github.com/octocat/activity/pull/{}", number)),
+            merged: status == RunStatus::Succeeded && pr_number.is_some(),
+            closed: false,
+            files_changed: if status == RunStatus::Succeeded { 3 } else { 0 },
+            lines_changed: if status == RunStatus::Succeeded { 20 } else { 0 },
+            lines_added: None,
+            lines_deleted: None,
+            pr_open_secs: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn digest_summarizes_the_past_week() {
+        let now = Utc.with_ymd_and_hms(2024, 5, 8, 9, 0, 0).unwrap();
+        let history = vec![
+            // Older than the digest period
+            run(Utc.with_ymd_and_hms(2024, 4, 30, 8, 0, 0).unwrap(), RunStatus::Succeeded, Some(1)),
+            run(Utc.with_ymd_and_hms(2024, 5, 2, 8, 0, 0).unwrap(), RunStatus::Succeeded, Some(2)),
+            run(Utc.with_ymd_and_hms(2024, 5, 3, 8, 0, 0).unwrap(), RunStatus::Failed, None),
+            run(Utc.with_ymd_and_hms(2024, 5, 3, 16, 0, 0).unwrap(), RunStatus::Skipped, None),
+            run(Utc.with_ymd_and_hms(2024, 5, 6, 8, 30, 0).unwrap(), RunStatus::Succeeded, Some(3)),
+        ];
+
+        let digest = format_digest("octocat/activity", &history, now);
+
+        assert_eq!(digest, "\
+Activity digest for octocat/activity, 2024-05-01 to 2024-05-08
+Runs: 4 attempted, 2 succeeded, 1 skipped, 1 failed
+PRs merged: 2
+  - #2 https://github.com/octocat/activity/pull/2
+  - #3 https://github.com/octocat/activity/pull/3
+Changes: 6 files, 40 lines
+Longest gap between runs: 2d 16h 30m
+Days without activity: 2024-05-03, 2024-05-04, 2024-05-05, 2024-05-07, 2024-05-08");
+    }
+}
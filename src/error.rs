@@ -0,0 +1,89 @@
+use http::StatusCode;
+use std::fmt;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// Errors the bot reports with more detail than a message
+#[derive(Debug)]
+pub enum BotError {
+    /// A `git` subprocess exited with a nonzero status
+    GitCommand {
+        args: Vec<String>,
+        status: ExitStatus,
+        stdout: String,
+        stderr: String,
+    },
+    /// A `git` subprocess was killed after running longer than its timeout
+    GitTimeout {
+        args: Vec<String>,
+        timeout: Duration,
+    },
+    /// A GitHub API request failed before a response could be read
+    Api(Box<dyn std::error::Error + Send + Sync>),
+    /// GitHub answered a request with an unexpected status
+    GitHub {
+        route: String,
+        status: StatusCode,
+        body: String,
+    },
+    Io(std::io::Error),
+    /// A phase of a run took longer than its `phase_budgets` entry
+    PhaseTimeout {
+        phase: &'static str,
+        timeout: Duration,
+    },
+    /// A whole run took longer than `max_run_duration_secs` and was aborted
+    RunTimeout {
+        timeout: Duration,
+    },
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BotError::GitCommand { args, status, stdout, stderr } => {
+                write!(f, "git {} failed with {}", args.join(" "), status)?;
+                // git writes most errors to stderr, but some (e.g. merge conflicts) to stdout
+                let output = if stderr.trim().is_empty() { stdout } else { stderr };
+                if !output.trim().is_empty() {
+                    write!(f, ": {}", output.trim())?;
+                }
+                Ok(())
+            }
+            BotError::GitTimeout { args, timeout } => {
+                write!(f, "git {} timed out after {}s and was killed", args.join(" "), timeout.as_secs())
+            }
+            BotError::Api(e) => write!(f, "GitHub API request failed: {}", e),
+            BotError::GitHub { route, status, body } => {
+                write!(f, "GitHub responded to {} with {}", route, status)?;
+                if !body.trim().is_empty() {
+                    write!(f, ": {}", body.trim())?;
+                }
+                Ok(())
+            }
+            BotError::Io(e) => write!(f, "{}", e),
+            BotError::PhaseTimeout { phase, timeout } => {
+                write!(f, "The {} phase took longer than its budget of {}s", phase, timeout.as_secs())
+            }
+            BotError::RunTimeout { timeout } => {
+                write!(f, "The run took longer than max_run_duration_secs ({}s) and was aborted", timeout.as_secs())
+            }
+        }
+    }
+}
+
+impl std::error::Error for BotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BotError::Api(e) => Some(e.as_ref()),
+            BotError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BotError {
+    fn from(e: std::io::Error) -> Self {
+        BotError::Io(e)
+    }
+}
@@ -0,0 +1,56 @@
+use chrono::{DateTime, Utc};
+use sentry::protocol::{Context, Map, Value};
+
+use crate::state::RunState;
+
+/// Start sending errors to Sentry. Events are only sent while the returned guard is alive.
+pub fn init(dsn: &str) -> sentry::ClientInitGuard {
+    sentry::init((dsn, sentry::ClientOptions {
+        release: sentry::release_name!(),
+        ..Default::default()
+    }))
+}
+
+/// Context attached to the Sentry event of a failed run
+pub fn run_context(run_id: &str, started_at: DateTime<Utc>, run: Option<&RunState>) -> Map<String, Value> {
+    let mut context = Map::new();
+    context.insert("run_id".to_string(), run_id.into());
+    context.insert("started_at".to_string(), started_at.to_rfc3339().into());
+    if let Some(run) = run {
+        context.insert("branch_name".to_string(), run.branch_name.clone().into());
+        if let Some(pr_number) = run.pr_number {
+            context.insert("pr_number".to_string(), pr_number.into());
+        }
+    }
+    context
+}
+
+/// Capture the error a run failed with as a Sentry exception; does nothing without `sentry_dsn`
+pub fn capture_run_error(error: &(dyn std::error::Error + 'static), run_id: &str, started_at: DateTime<Utc>, run: Option<&RunState>) {
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("run_id", run_id);
+            scope.set_context("run", Context::Other(run_context(run_id, started_at, run)));
+        },
+        || sentry::capture_error(error),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn run_context_names_the_branch_and_pr() {
+        let started_at = Utc.with_ymd_and_hms(2024, 5, 1, 9, 30, 0).unwrap();
+        let run = RunState { branch_name: "bot-update-1".to_string(), pr_number: Some(42), pushed: true, merged: false };
+
+        let context = run_context("run-1", started_at, Some(&run));
+
+        assert_eq!(context["branch_name"], "bot-update-1");
+        assert_eq!(context["pr_number"], 42);
+        assert_eq!(context["started_at"], "2024-05-01T09:30:00+00:00");
+        assert!(!run_context("run-1", started_at, None).contains_key("branch_name"));
+    }
+}
@@ -0,0 +1,95 @@
+use http::header::HeaderMap;
+use octocrab::{FromResponse, Octocrab};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The bot's only way to call the GitHub API. Retries and timeouts are layers of the client
+/// `client::build_octocrab` builds from the `[http]` table, so every call gets them; this
+/// wrapper adds the request count reported after each run.
+#[derive(Clone)]
+pub struct GhApi {
+    octocrab: Octocrab,
+    /// Requests made through this client and its clones
+    requests: Arc<AtomicU64>,
+}
+
+impl GhApi {
+    pub fn new(octocrab: Octocrab) -> Self {
+        Self { octocrab, requests: Arc::new(AtomicU64::new(0)) }
+    }
+
+    /// Number of requests made so far
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    fn count(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Octocrab's typed handlers such as `pulls` and `repos`, for a single request
+    pub fn typed(&self) -> &Octocrab {
+        self.count();
+        &self.octocrab
+    }
+
+    /// GET `route` and parse the response
+    pub async fn get<R: FromResponse>(&self, route: impl AsRef<str>) -> octocrab::Result<R> {
+        self.count();
+        self.octocrab.get(route, None::<&()>).await
+    }
+
+    /// POST `body` as JSON to `route` and parse the response
+    pub async fn post<B: Serialize + ?Sized, R: FromResponse>(&self, route: impl AsRef<str>, body: &B) -> octocrab::Result<R> {
+        self.count();
+        self.octocrab.post(route, Some(body)).await
+    }
+
+    /// PATCH `route` with `body` as JSON and parse the response
+    pub async fn patch<B: Serialize + ?Sized, R: FromResponse>(&self, route: impl AsRef<str>, body: &B) -> octocrab::Result<R> {
+        self.count();
+        self.octocrab.patch(route, Some(body)).await
+    }
+
+    /// GET `route`, returning the raw response whatever its status
+    pub async fn get_response(&self, route: &str) -> octocrab::Result<http::Response<hyper::Body>> {
+        self.count();
+        self.octocrab._get(route).await
+    }
+
+    /// GET `route` with extra headers, returning the raw response whatever its status
+    pub async fn get_response_with_headers(&self, route: &str, headers: HeaderMap) -> octocrab::Result<http::Response<hyper::Body>> {
+        self.count();
+        self.octocrab._get_with_headers(route, Some(headers)).await
+    }
+
+    /// POST `body` as JSON to `route`, returning the raw response whatever its status
+    pub async fn post_response<B: Serialize + ?Sized>(&self, route: &str, body: &B) -> octocrab::Result<http::Response<hyper::Body>> {
+        self.count();
+        self.octocrab._post(route, Some(body)).await
+    }
+
+    /// PUT `route` without a body, returning the raw response whatever its status
+    pub async fn put_response(&self, route: &str) -> octocrab::Result<http::Response<hyper::Body>> {
+        self.count();
+        self.octocrab._put(route, None::<&()>).await
+    }
+
+    /// PUT `body` as JSON to `route` with extra headers, returning the raw response whatever its status
+    pub async fn put_response_with_headers<B: Serialize + ?Sized>(&self, route: &str, body: &B, headers: HeaderMap) -> octocrab::Result<http::Response<hyper::Body>> {
+        self.count();
+        let mut builder = http::Request::builder().method(http::Method::PUT).uri(route);
+        for (name, value) in &headers {
+            builder = builder.header(name, value);
+        }
+        let request = self.octocrab.build_request(builder, Some(body))?;
+        self.octocrab.execute(request).await
+    }
+
+    /// DELETE `route`, returning the raw response whatever its status
+    pub async fn delete_response(&self, route: &str) -> octocrab::Result<http::Response<hyper::Body>> {
+        self.count();
+        self.octocrab._delete(route, None::<&()>).await
+    }
+}
@@ -0,0 +1,149 @@
+use chrono::{DateTime, Utc};
+use rand::{rngs::StdRng, Rng};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use crate::{content, GitHubBot, RunReport};
+
+/// The `[gist_activity]` config section: runs that create or update a gist instead of opening a PR
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GistActivityConfig {
+    /// Chance that a run works on the gist instead of the repository
+    #[serde(default)]
+    pub probability: f64,
+    /// Id of the gist to update
+    #[serde(default)]
+    pub gist_id: Option<String>,
+    /// Create a new gist on every gist run instead of updating `gist_id`
+    #[serde(default)]
+    pub create_new: bool,
+    /// Name of the gist's file; `{date}` is replaced with the run's date
+    #[serde(default = "default_filename")]
+    pub filename: String,
+    /// Content of the file; `{date}` and `{timestamp}` are replaced, and `{lines}` with
+    /// `min_lines` to `max_lines` generated lines in the style of the file's extension
+    #[serde(default = "default_content")]
+    pub content: String,
+    /// Whether new gists are public rather than secret
+    #[serde(default)]
+    pub public: bool,
+}
+
+fn default_filename() -> String {
+    "notes.md".to_string()
+}
+
+fn default_content() -> String {
+    "{lines}".to_string()
+}
+
+impl GistActivityConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.probability) {
+            return Err("gist_activity.probability must be between 0 and 1".to_string());
+        }
+        match (&self.gist_id, self.create_new) {
+            (Some(_), true) => return Err("gist_activity takes either gist_id or create_new, not both".to_string()),
+            (None, false) => return Err("gist_activity needs a gist_id to update or create_new = true".to_string()),
+            _ => {}
+        }
+        if self.filename.trim().is_empty() || self.filename.contains('/') {
+            return Err("gist_activity.filename must be a file name without slashes".to_string());
+        }
+        // Classic tokens need the `gist` scope; that is checked against GitHub at startup
+        Ok(())
+    }
+}
+
+/// Fill in a gist template for a run at `now`
+pub fn render(template: &str, now: DateTime<Utc>, lines: &str) -> String {
+    template
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+        .replace("{timestamp}", &now.format("%Y-%m-%d %H:%M:%S").to_string())
+        .replace("{lines}", lines)
+}
+
+/// Explain why the token can't write gists, if it can't. `scopes` is the `X-OAuth-Scopes`
+/// header, which only classic tokens send.
+pub fn gist_scope_problem(scopes: Option<&str>) -> Option<String> {
+    let scopes = scopes?;
+    if scopes.split(',').map(str::trim).any(|scope| scope == "gist") {
+        return None;
+    }
+    Some("gist_activity needs the token's `gist` scope".to_string())
+}
+
+impl GitHubBot {
+    /// Fail at startup when a classic token lacks the `gist` scope `gist_activity` needs
+    pub(crate) async fn check_gist_scope(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let response = self.api.get_response("/user").await?;
+        let scopes = response.headers()
+            .get("x-oauth-scopes")
+            .and_then(|value| value.to_str().ok());
+        match gist_scope_problem(scopes) {
+            Some(problem) => Err(problem.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Create or update the gist from `gist_activity` instead of going through the repository
+    pub(crate) async fn run_gist(&self, run_id: &str, gist: &GistActivityConfig, rng: &mut StdRng) -> Result<RunReport, Box<dyn std::error::Error>> {
+        let mut report = RunReport::new(run_id.to_string());
+        println!("Starting gist run at {}", report.started_at);
+
+        let now = Utc::now();
+        let filename = render(&gist.filename, now, "");
+        let num_lines = rng.gen_range(self.config.min_lines..=self.config.max_lines);
+        let lines = content::strategy_for(&filename, self.config.random_seed.is_some()).generate(num_lines, rng);
+        let text = render(&gist.content, now, &lines);
+        report.lines_changed = text.lines().count();
+        report.files_changed = Some(1);
+
+        let files = json!({ filename.as_str(): { "content": text } });
+        let response: Value = match &gist.gist_id {
+            Some(gist_id) => self.api
+                .patch(format!("/gists/{}", gist_id), &json!({ "files": files }))
+                .await
+                .map_err(|e| format!("Could not update gist {}: {}", gist_id, e))?,
+            None => self.api
+                .post("/gists", &json!({ "description": "Notes", "public": gist.public, "files": files }))
+                .await
+                .map_err(|e| format!("Could not create a gist: {}", e))?,
+        };
+        report.gist_url = response["html_url"].as_str().map(str::to_string);
+        println!("Wrote {} to gist {}", filename, report.gist_url.as_deref().unwrap_or_default());
+
+        report.finished_at = Some(Utc::now());
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn gist_config_templates_and_scopes() {
+        let config: GistActivityConfig = toml::from_str(r#"
+            probability = 0.2
+            gist_id = "abc123"
+            filename = "log-{date}.txt"
+            content = "Updated {timestamp}\n{lines}"
+        "#).unwrap();
+        assert!(config.validate().is_ok());
+        assert!(!config.public);
+        let both = GistActivityConfig { create_new: true, ..config.clone() };
+        assert!(both.validate().is_err());
+        let neither = GistActivityConfig { gist_id: None, ..config.clone() };
+        assert!(neither.validate().is_err());
+
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 9, 30, 0).unwrap();
+        assert_eq!(render(&config.filename, now, ""), "log-2024-05-01.txt");
+        assert_eq!(render(&config.content, now, "Line 1\n"), "Updated 2024-05-01 09:30:00\nLine 1\n");
+
+        assert_eq!(gist_scope_problem(None), None);
+        assert_eq!(gist_scope_problem(Some("repo, gist")), None);
+        assert!(gist_scope_problem(Some("repo, workflow")).is_some());
+    }
+}
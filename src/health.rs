@@ -0,0 +1,109 @@
+use chrono::{DateTime, TimeZone, Utc};
+use http::StatusCode;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+use crate::error::BotError;
+use crate::GitHubBot;
+
+/// What the bot can see of GitHub before it runs
+#[derive(Debug, Serialize, PartialEq)]
+pub struct HealthReport {
+    pub api_reachable: bool,
+    /// Login the token authenticates as; empty when the API can't be reached
+    pub username: String,
+    pub rate_limit_remaining: u32,
+    /// When the rate limit window resets
+    pub rate_limit_reset: Option<DateTime<Utc>>,
+    pub repo_exists: bool,
+    pub default_branch: Option<String>,
+}
+
+impl HealthReport {
+    fn unreachable() -> Self {
+        HealthReport {
+            api_reachable: false,
+            username: String::new(),
+            rate_limit_remaining: 0,
+            rate_limit_reset: None,
+            repo_exists: false,
+            default_branch: None,
+        }
+    }
+
+    /// Whether a run could start: the API answers and the repository exists
+    pub fn is_healthy(&self) -> bool {
+        self.api_reachable && self.repo_exists
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        writeln!(f, "GitHub health:")?;
+        writeln!(f, "  API reachable:       {}", yes_no(self.api_reachable))?;
+        if !self.api_reachable {
+            return Ok(());
+        }
+        writeln!(f, "  authenticated as:    {}", self.username)?;
+        write!(f, "  rate limit:          {} remaining", self.rate_limit_remaining)?;
+        if let Some(reset) = self.rate_limit_reset {
+            write!(f, ", resets at {}", reset.format("%Y-%m-%d %H:%M:%S UTC"))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  repository exists:   {}", yes_no(self.repo_exists))?;
+        write!(f, "  default branch:      {}", self.default_branch.as_deref().unwrap_or("n/a"))
+    }
+}
+
+impl GitHubBot {
+    /// Check that the API answers, the token is accepted and the repository exists, without
+    /// touching the working tree. An unreachable API is reported rather than returned as an error.
+    pub(crate) async fn check_health(&self) -> Result<HealthReport, BotError> {
+        let user = match self.get_json("/user").await {
+            Ok(user) => user,
+            Err(BotError::Api(e)) => {
+                if self.config.debug {
+                    println!("GitHub API is unreachable: {}", e);
+                }
+                return Ok(HealthReport::unreachable());
+            }
+            Err(e) => return Err(e),
+        };
+
+        let rate_limit = self.get_json("/rate_limit").await?;
+        let core = &rate_limit["resources"]["core"];
+
+        let repo_route = format!("/repos/{}/{}", self.repo_owner, self.repo_name);
+        let (repo_exists, default_branch) = match self.get_json(&repo_route).await {
+            Ok(repo) => (true, repo["default_branch"].as_str().map(String::from)),
+            Err(BotError::GitHub { status: StatusCode::NOT_FOUND, .. }) => (false, None),
+            Err(e) => return Err(e),
+        };
+
+        Ok(HealthReport {
+            api_reachable: true,
+            username: user["login"].as_str().unwrap_or_default().to_string(),
+            rate_limit_remaining: core["remaining"].as_u64().unwrap_or(0) as u32,
+            rate_limit_reset: core["reset"].as_i64().and_then(|reset| Utc.timestamp_opt(reset, 0).single()),
+            repo_exists,
+            default_branch,
+        })
+    }
+
+    /// GET `route` and parse the JSON body, turning unsuccessful statuses into errors
+    async fn get_json(&self, route: &str) -> Result<Value, BotError> {
+        let response = self.api.get_response(route).await.map_err(|e| BotError::Api(e.into()))?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.map_err(|e| BotError::Api(e.into()))?;
+        if !status.is_success() {
+            return Err(BotError::GitHub {
+                route: route.to_string(),
+                status,
+                body: String::from_utf8_lossy(&body).to_string(),
+            });
+        }
+        Ok(serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+}
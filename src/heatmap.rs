@@ -0,0 +1,332 @@
+use chrono::{Datelike, Duration, NaiveDate};
+use chrono_tz::Tz;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::state::{RunRecord, RunStatus};
+use crate::GitHubBot;
+
+/// Columns of the heatmap, like the contribution graph on a GitHub profile: the 52 weeks
+/// before the current one, and the current one
+pub const WEEKS: i64 = 53;
+
+/// Width of the weekday labels in front of the terminal rows
+const LABEL_WIDTH: usize = 4;
+const WEEKDAY_LABELS: [&str; 7] = ["", "Mon", "", "Wed", "", "Fri", ""];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+/// A day without contributions, then the four levels of activity
+const SHADES: [char; 5] = ['·', '░', '▒', '▓', '█'];
+const SVG_COLORS: [&str; 5] = ["#ebedf0", "#9be9a8", "#40c463", "#30a14e", "#216e39"];
+/// Size of an SVG cell and the distance between the starts of two cells
+const SVG_CELL: i64 = 10;
+const SVG_STEP: i64 = 13;
+const SVG_LEFT: i64 = 32;
+const SVG_TOP: i64 = 20;
+
+/// PRs the bot opened per day in `timezone`, from the run history
+pub fn bot_counts(history: &[RunRecord], timezone: Tz) -> BTreeMap<NaiveDate, u32> {
+    let mut counts = BTreeMap::new();
+    for run in history.iter().filter(|run| run.status == RunStatus::Succeeded && run.pr_number.is_some()) {
+        *counts.entry(run.started_at.with_timezone(&timezone).date_naive()).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Add GitHub's own counts. They already include the bot's contributions, so each day keeps
+/// the larger of the two.
+pub fn merge_counts(counts: &mut BTreeMap<NaiveDate, u32>, github: BTreeMap<NaiveDate, u32>) {
+    for (day, count) in github {
+        let entry = counts.entry(day).or_insert(0);
+        *entry = (*entry).max(count);
+    }
+}
+
+/// The Sunday the first column starts on, so that the last column holds `today`
+pub fn first_day(today: NaiveDate) -> NaiveDate {
+    let this_sunday = today - Duration::days(today.weekday().num_days_from_sunday() as i64);
+    this_sunday - Duration::weeks(WEEKS - 1)
+}
+
+/// The days of a `contributionsCollection` query's contribution calendar
+pub fn parse_contribution_days(response: &Value) -> BTreeMap<NaiveDate, u32> {
+    let weeks = response["data"]["user"]["contributionsCollection"]["contributionCalendar"]["weeks"].as_array();
+    weeks.into_iter()
+        .flatten()
+        .filter_map(|week| week["contributionDays"].as_array())
+        .flatten()
+        .filter_map(|day| {
+            let date = NaiveDate::parse_from_str(day["date"].as_str()?, "%Y-%m-%d").ok()?;
+            Some((date, day["contributionCount"].as_u64()? as u32))
+        })
+        .collect()
+}
+
+/// Contributions per day over the weeks ending with `today`'s, as printed by `--heatmap`
+pub struct Heatmap {
+    start: NaiveDate,
+    today: NaiveDate,
+    counts: BTreeMap<NaiveDate, u32>,
+}
+
+impl Heatmap {
+    pub fn new(counts: BTreeMap<NaiveDate, u32>, today: NaiveDate) -> Self {
+        Heatmap { start: first_day(today), today, counts }
+    }
+
+    /// Day in column `week` and row `weekday`, counted from Sunday
+    fn day(&self, week: i64, weekday: i64) -> NaiveDate {
+        self.start + Duration::days(week * 7 + weekday)
+    }
+
+    fn count(&self, day: NaiveDate) -> u32 {
+        self.counts.get(&day).copied().unwrap_or(0)
+    }
+
+    fn shown_counts(&self) -> impl Iterator<Item = u32> + '_ {
+        self.counts.range(self.start..=self.today).map(|(_, count)| *count)
+    }
+
+    pub fn total(&self) -> u32 {
+        self.shown_counts().sum()
+    }
+
+    /// 0 for no contributions, otherwise 1 to 4 relative to the busiest day
+    fn level(&self, count: u32) -> usize {
+        let max = self.shown_counts().max().unwrap_or(0);
+        if count == 0 || max == 0 {
+            return 0;
+        }
+        ((count * 4).div_ceil(max) as usize).clamp(1, 4)
+    }
+
+    /// Columns without a single contribution up to today
+    pub fn empty_weeks(&self) -> Vec<i64> {
+        (0..WEEKS)
+            .filter(|week| {
+                (0..7)
+                    .map(|weekday| self.day(*week, weekday))
+                    .take_while(|day| *day <= self.today)
+                    .all(|day| self.count(day) == 0)
+            })
+            .collect()
+    }
+
+    /// Columns a new month starts in, with the month's name
+    fn month_labels(&self) -> Vec<(i64, &'static str)> {
+        (0..WEEKS)
+            .filter(|week| *week == 0 || self.day(*week, 0).month() != self.day(week - 1, 0).month())
+            .map(|week| (week, MONTHS[self.day(week, 0).month0() as usize]))
+            .collect()
+    }
+
+    /// Rows of unicode blocks for the terminal, with a legend
+    pub fn render_terminal(&self) -> String {
+        let mut header = " ".repeat(LABEL_WIDTH);
+        for (week, month) in self.month_labels() {
+            let column = LABEL_WIDTH + week as usize * 2;
+            // A month starting right after the previous label has no room for its own
+            if header.chars().count() <= column {
+                header.push_str(&" ".repeat(column - header.chars().count()));
+                header.push_str(month);
+            }
+        }
+        let mut out = format!("{}\n", header.trim_end());
+
+        for weekday in 0..7 {
+            let mut row = format!("{:<width$}", WEEKDAY_LABELS[weekday as usize], width = LABEL_WIDTH);
+            for week in 0..WEEKS {
+                let day = self.day(week, weekday);
+                if day > self.today {
+                    break;
+                }
+                row.push(SHADES[self.level(self.count(day))]);
+                row.push(' ');
+            }
+            out.push_str(row.trim_end());
+            out.push('\n');
+        }
+
+        let empty_weeks = self.empty_weeks();
+        if !empty_weeks.is_empty() {
+            let mut marks = " ".repeat(LABEL_WIDTH);
+            for week in 0..WEEKS {
+                marks.push_str(if empty_weeks.contains(&week) { "! " } else { "  " });
+            }
+            out.push_str(marks.trim_end());
+            out.push('\n');
+        }
+
+        let shades: Vec<String> = SHADES.iter().map(char::to_string).collect();
+        out.push_str(&format!("\n{}Less {} More    ! week without activity\n", " ".repeat(LABEL_WIDTH), shades.join(" ")));
+        out.push_str(&format!(
+            "{}{} contributions from {} to {}, {} week(s) without any\n",
+            " ".repeat(LABEL_WIDTH), self.total(), self.start, self.today, empty_weeks.len()
+        ));
+        out
+    }
+
+    /// The same heatmap as an SVG image; weeks without activity are outlined in red
+    pub fn render_svg(&self) -> String {
+        let width = SVG_LEFT + WEEKS * SVG_STEP + SVG_CELL;
+        let height = SVG_TOP + 7 * SVG_STEP + 3 * SVG_STEP;
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"9\">\n",
+            width, height
+        );
+        for (week, month) in self.month_labels() {
+            svg.push_str(&format!("  <text x=\"{}\" y=\"{}\">{}</text>\n", SVG_LEFT + week * SVG_STEP, SVG_TOP - 6, month));
+        }
+        for (weekday, label) in WEEKDAY_LABELS.iter().enumerate().filter(|(_, label)| !label.is_empty()) {
+            svg.push_str(&format!("  <text x=\"0\" y=\"{}\">{}</text>\n", SVG_TOP + weekday as i64 * SVG_STEP + SVG_CELL - 1, label));
+        }
+        for week in self.empty_weeks() {
+            svg.push_str(&format!(
+                "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"2\" fill=\"#ffebe9\" stroke=\"#cf222e\"/>\n",
+                SVG_LEFT + week * SVG_STEP - 1, SVG_TOP - 1, SVG_CELL + 2, 6 * SVG_STEP + SVG_CELL + 2
+            ));
+        }
+        for week in 0..WEEKS {
+            for weekday in 0..7 {
+                let day = self.day(week, weekday);
+                if day > self.today {
+                    break;
+                }
+                let count = self.count(day);
+                svg.push_str(&format!(
+                    "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"2\" fill=\"{}\"><title>{}: {} contribution(s)</title></rect>\n",
+                    SVG_LEFT + week * SVG_STEP, SVG_TOP + weekday * SVG_STEP, SVG_CELL, SVG_CELL,
+                    SVG_COLORS[self.level(count)], day, count
+                ));
+            }
+        }
+
+        let legend_y = SVG_TOP + 7 * SVG_STEP + SVG_STEP;
+        svg.push_str(&format!("  <text x=\"{}\" y=\"{}\">Less</text>\n", SVG_LEFT, legend_y + SVG_CELL - 1));
+        for (level, color) in SVG_COLORS.iter().enumerate() {
+            svg.push_str(&format!(
+                "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"2\" fill=\"{}\"/>\n",
+                SVG_LEFT + 24 + level as i64 * SVG_STEP, legend_y, SVG_CELL, SVG_CELL, color
+            ));
+        }
+        svg.push_str(&format!(
+            "  <text x=\"{}\" y=\"{}\">More; {} contributions, {} week(s) without any</text>\n",
+            SVG_LEFT + 24 + 5 * SVG_STEP + 2, legend_y + SVG_CELL - 1, self.total(), self.empty_weeks().len()
+        ));
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+impl GitHubBot {
+    /// Contributions per day from `from` to `to` on `username`'s GitHub profile
+    pub(crate) async fn github_contribution_counts(&self, from: NaiveDate, to: NaiveDate) -> Result<BTreeMap<NaiveDate, u32>, Box<dyn std::error::Error>> {
+        let mut counts = BTreeMap::new();
+        let mut chunk_start = from;
+        // contributionsCollection spans at most a year
+        while chunk_start <= to {
+            let chunk_end = (chunk_start + Duration::days(364)).min(to);
+            let response: Value = self.api.typed()
+                .graphql(&json!({
+                    "query": "query($login: String!, $from: DateTime!, $to: DateTime!) {
+                        user(login: $login) {
+                            contributionsCollection(from: $from, to: $to) {
+                                contributionCalendar { weeks { contributionDays { date contributionCount } } }
+                            }
+                        }
+                    }",
+                    "variables": {
+                        "login": self.config.username,
+                        "from": format!("{}T00:00:00Z", chunk_start),
+                        "to": format!("{}T23:59:59Z", chunk_end),
+                    },
+                }))
+                .await?;
+            if let Some(errors) = response.get("errors").and_then(|e| e.as_array()) {
+                let messages: Vec<&str> = errors
+                    .iter()
+                    .filter_map(|e| e.get("message").and_then(|m| m.as_str()))
+                    .collect();
+                return Err(messages.join("; ").into());
+            }
+            counts.extend(parse_contribution_days(&response));
+            chunk_start = chunk_end + Duration::days(1);
+        }
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::RunType;
+    use chrono::{TimeZone, Utc};
+
+    fn pr_run(started_at: chrono::DateTime<Utc>) -> RunRecord {
+        RunRecord {
+            run_id: None,
+            run_type: RunType::Pr,
+            started_at,
+            status: RunStatus::Succeeded,
+            pr_number: Some(1),
+            pr_url: None,
+            merged: true,
+            closed: false,
+            files_changed: 1,
+            lines_changed: 5,
+            lines_added: None,
+            lines_deleted: None,
+            pr_open_secs: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn days_are_counted_in_the_timezone_across_the_year_boundary() {
+        // 02:00 UTC on New Year's Day is still New Year's Eve in New York
+        let history = vec![
+            pr_run(Utc.with_ymd_and_hms(2024, 1, 1, 2, 0, 0).unwrap()),
+            pr_run(Utc.with_ymd_and_hms(2024, 1, 1, 18, 0, 0).unwrap()),
+        ];
+        let counts = bot_counts(&history, chrono_tz::America::New_York);
+        assert_eq!(counts.get(&NaiveDate::from_ymd_opt(2023, 12, 31).unwrap()), Some(&1));
+        assert_eq!(counts.get(&NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()), Some(&1));
+        assert_eq!(bot_counts(&history, chrono_tz::UTC).get(&NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()), Some(&2));
+
+        // Wednesday 2024-01-03: the first column starts on the Sunday 52 weeks before that week's
+        let today = NaiveDate::from_ymd_opt(2024, 1, 3).unwrap();
+        assert_eq!(first_day(today), NaiveDate::from_ymd_opt(2023, 1, 1).unwrap());
+
+        let mut counts = counts;
+        merge_counts(&mut counts, BTreeMap::from([(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(), 3)]));
+        let heatmap = Heatmap::new(counts, today);
+        assert_eq!(heatmap.total(), 4);
+        // Only the last column, the week of 2023-12-31, has contributions
+        assert_eq!(heatmap.empty_weeks().len(), WEEKS as usize - 1);
+        assert_eq!(heatmap.month_labels().last(), Some(&(48, "Dec")));
+
+        let terminal = heatmap.render_terminal();
+        let lines: Vec<&str> = terminal.lines().collect();
+        assert!(lines[0].starts_with("    Jan"), "{}", lines[0]);
+        // Monday 2024-01-01 is the busiest day; Sunday 2023-12-31 has a third of its contributions
+        assert!(lines[2].ends_with('█'), "{}", lines[2]);
+        assert!(lines[1].ends_with("· ▒"), "{}", lines[1]);
+        // Today's Wednesday is the last day shown
+        assert!(lines[4].ends_with('·') && lines[5].trim_end().chars().count() < lines[4].chars().count());
+        assert!(terminal.contains("4 contributions from 2023-01-01 to 2024-01-03, 52 week(s) without any"));
+
+        let svg = heatmap.render_svg();
+        assert!(svg.contains("<title>2024-01-01: 3 contribution(s)</title>"));
+        assert!(!svg.contains("2024-01-04"));
+    }
+
+    #[test]
+    fn contribution_calendars_are_parsed() {
+        let response = json!({ "data": { "user": { "contributionsCollection": { "contributionCalendar": { "weeks": [
+            { "contributionDays": [{ "date": "2023-12-31", "contributionCount": 2 }, { "date": "2024-01-01", "contributionCount": 0 }] },
+        ] } } } } });
+        let days = parse_contribution_days(&response);
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[&NaiveDate::from_ymd_opt(2023, 12, 31).unwrap()], 2);
+        assert!(parse_contribution_days(&json!({ "errors": [] })).is_empty());
+    }
+}
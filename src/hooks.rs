@@ -0,0 +1,118 @@
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+/// Run shell commands one after another in `dir`, stopping at the first one that fails
+pub async fn run_hooks(
+    commands: &[String],
+    dir: &Path,
+    env: &[(&str, String)],
+    timeout: Duration,
+    debug: bool,
+) -> Result<(), String> {
+    for command in commands {
+        run_hook(command, dir, env, timeout, debug).await?;
+    }
+    Ok(())
+}
+
+/// Run a shell command in `dir`, killing it after `timeout`. Its output goes to the debug log.
+pub async fn run_hook(
+    command: &str,
+    dir: &Path,
+    env: &[(&str, String)],
+    timeout: Duration,
+    debug: bool,
+) -> Result<(), String> {
+    if debug {
+        println!("Running hook: {}", command);
+    }
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(dir)
+        .envs(env.iter().map(|(name, value)| (*name, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to start hook '{}': {}", command, e))?;
+
+    // Drain the pipes on their own tasks so a chatty hook can't block on a full pipe
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let waited = time::timeout(timeout, child.wait()).await;
+    let status = match waited {
+        Ok(status) => status.map_err(|e| format!("Failed to wait for hook '{}': {}", command, e))?,
+        Err(_) => {
+            // Processes the hook started may still hold the pipes open, so don't wait for its output
+            let _ = child.kill().await;
+            stdout.abort();
+            stderr.abort();
+            return Err(format!("Hook '{}' timed out after {}s", command, timeout.as_secs()));
+        }
+    };
+    let stdout = stdout.await.unwrap_or_default();
+    let stderr = stderr.await.unwrap_or_default();
+
+    if debug {
+        if !stdout.trim().is_empty() {
+            println!("Hook stdout:\n{}", stdout.trim_end());
+        }
+        if !stderr.trim().is_empty() {
+            println!("Hook stderr:\n{}", stderr.trim_end());
+        }
+    }
+
+    if !status.success() {
+        return Err(format!("Hook '{}' failed with {}: {}", command, status, stderr.trim()));
+    }
+
+    Ok(())
+}
+
+fn read_in_background<R: AsyncRead + Unpin + Send + 'static>(pipe: Option<R>) -> JoinHandle<String> {
+    tokio::spawn(async move {
+        let mut output = String::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_string(&mut output).await;
+        }
+        output
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn hooks_see_the_environment_and_report_failures() {
+        let dir = std::env::temp_dir();
+        let env = [("BOT_BRANCH", "bot-update-1".to_string())];
+        let timeout = Duration::from_secs(5);
+
+        assert!(run_hook("test \"$BOT_BRANCH\" = bot-update-1", &dir, &env, timeout, false).await.is_ok());
+
+        let error = run_hook("echo broken >&2; exit 3", &dir, &env, timeout, false).await.unwrap_err();
+        assert!(error.contains("broken"), "{}", error);
+
+        let error = run_hook("sleep 5", &dir, &env, Duration::from_millis(200), false).await.unwrap_err();
+        assert!(error.contains("timed out"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn hooks_stop_at_the_first_failure() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let commands = vec!["exit 1".to_string(), "touch ran".to_string()];
+
+        assert!(run_hooks(&commands, dir.path(), &[], Duration::from_secs(5), false).await.is_err());
+        assert!(!dir.path().join("ran").exists());
+    }
+}
@@ -0,0 +1,93 @@
+use rand::{rngs::StdRng, Rng};
+use std::time::Duration;
+use tokio::time;
+
+use crate::wait::{sample_wait, WaitDistribution};
+use crate::{Config, CountRange, WaitRange};
+
+/// Random pauses between the git steps of a run with `humanize`, so its reflog doesn't show
+/// a checkout, commit and push within milliseconds of each other
+pub struct Humanizer {
+    /// Range of each pause; `None` when `humanize` is off
+    delay_secs: Option<WaitRange>,
+    distribution: WaitDistribution,
+    burst_files: CountRange,
+    debug: bool,
+    /// Time spent pausing so far
+    total: Duration,
+}
+
+impl Humanizer {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            delay_secs: config.humanize.then_some(config.humanize_delay_secs),
+            distribution: config.wait_distribution,
+            burst_files: config.humanize_burst_files,
+            debug: config.debug,
+            total: Duration::ZERO,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.delay_secs.is_some()
+    }
+
+    /// Time spent pausing so far
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Wait a random time before the next step. Draws nothing from `rng` when off, so seeded
+    /// runs are unaffected.
+    pub async fn pause(&mut self, next_step: &str, rng: &mut StdRng) {
+        let Some(range) = self.delay_secs else {
+            return;
+        };
+        let secs = sample_wait(range.min, range.max, self.distribution, rng);
+        if self.debug {
+            println!("Pausing {}s before {}", secs, next_step);
+        }
+        time::sleep(Duration::from_secs(secs)).await;
+        self.total += Duration::from_secs(secs);
+    }
+
+    /// Sizes of the bursts `files` files are written in, with a pause between bursts;
+    /// a single burst when off
+    pub fn bursts(&self, files: usize, rng: &mut StdRng) -> Vec<usize> {
+        if !self.enabled() {
+            return vec![files];
+        }
+        split_into_bursts(files, self.burst_files, rng)
+    }
+}
+
+/// Split `files` into bursts of `burst_files.min` to `burst_files.max` files; the last one may be smaller
+pub fn split_into_bursts(files: usize, burst_files: CountRange, rng: &mut StdRng) -> Vec<usize> {
+    let mut bursts = Vec::new();
+    let mut left = files;
+    while left > 0 {
+        let size = (rng.gen_range(burst_files.min..=burst_files.max) as usize).clamp(1, left);
+        bursts.push(size);
+        left -= size;
+    }
+    bursts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn files_are_split_into_bursts_within_the_range() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for files in 0..20 {
+            let bursts = split_into_bursts(files, CountRange { min: 2, max: 4 }, &mut rng);
+            assert_eq!(bursts.iter().sum::<usize>(), files);
+            assert!(bursts.iter().all(|size| (1..=4).contains(size)), "{:?}", bursts);
+            if let Some((_, full)) = bursts.split_last() {
+                assert!(full.iter().all(|size| *size >= 2), "{:?}", bursts);
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use chrono::{Datelike, NaiveDate};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// The `[intensity]` config table: activity multipliers by month and weekday,
+/// e.g. `aug = 0.3` and `wed = 1.5`. Months and days that aren't listed count as 1.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(transparent)]
+pub struct Intensity(BTreeMap<String, f64>);
+
+impl Intensity {
+    pub fn validate(&self) -> Result<(), String> {
+        for (key, multiplier) in &self.0 {
+            if !MONTHS.contains(&key.as_str()) && !WEEKDAYS.contains(&key.as_str()) {
+                return Err(format!("intensity.{} is not a month (jan-dec) or weekday (mon-sun)", key));
+            }
+            if !multiplier.is_finite() || *multiplier < 0.0 {
+                return Err(format!("intensity.{} must not be negative", key));
+            }
+        }
+        let any_month = MONTHS.iter().any(|month| self.get(month) > 0.0);
+        let any_day = WEEKDAYS.iter().any(|day| self.get(day) > 0.0);
+        if !any_month || !any_day {
+            return Err("intensity must leave at least one day with a non-zero multiplier".to_string());
+        }
+        Ok(())
+    }
+
+    /// The multiplier for `date`: its month's times its weekday's
+    pub fn multiplier(&self, date: NaiveDate) -> f64 {
+        let month = MONTHS[date.month0() as usize];
+        let day = WEEKDAYS[date.weekday().num_days_from_monday() as usize];
+        self.get(month) * self.get(day)
+    }
+
+    fn get(&self, key: &str) -> f64 {
+        self.0.get(key).copied().unwrap_or(1.0)
+    }
+}
+
+/// Scale an inclusive range of counts by `multiplier`, keeping at least one
+pub fn scale_range(min: usize, max: usize, multiplier: f64) -> (usize, usize) {
+    let min = ((min as f64 * multiplier).round() as usize).max(1);
+    let max = ((max as f64 * multiplier).round() as usize).max(min);
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intensity(toml: &str) -> Intensity {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn multipliers_combine_month_and_weekday() {
+        let intensity = intensity("aug = 0.5\nwed = 1.5\nsun = 0");
+        assert!(intensity.validate().is_ok());
+
+        // 2024-08-07 is a Wednesday, 2024-05-08 too, 2024-08-11 a Sunday
+        assert_eq!(intensity.multiplier(NaiveDate::from_ymd_opt(2024, 8, 7).unwrap()), 0.75);
+        assert_eq!(intensity.multiplier(NaiveDate::from_ymd_opt(2024, 5, 8).unwrap()), 1.5);
+        assert_eq!(intensity.multiplier(NaiveDate::from_ymd_opt(2024, 8, 11).unwrap()), 0.0);
+        assert_eq!(Intensity::default().multiplier(NaiveDate::from_ymd_opt(2024, 8, 11).unwrap()), 1.0);
+
+        assert_eq!(scale_range(2, 6, 0.3), (1, 2));
+        assert_eq!(scale_range(2, 6, 1.5), (3, 9));
+    }
+
+    #[test]
+    fn validation_rejects_bad_profiles() {
+        assert!(intensity("aug = -1").validate().unwrap_err().contains("negative"));
+        assert!(intensity("august = 1").validate().unwrap_err().contains("not a month"));
+        let weekdays_off = "mon = 0\ntue = 0\nwed = 0\nthu = 0\nfri = 0\nsat = 0\nsun = 0";
+        assert!(intensity(weekdays_off).validate().unwrap_err().contains("non-zero"));
+        assert!(intensity("sat = 0\nsun = 0\njan = 0").validate().is_ok());
+    }
+}
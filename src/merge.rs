@@ -0,0 +1,205 @@
+use octocrab::models::pulls::MergeableState;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+use tokio::time;
+
+use crate::GitHubBot;
+
+/// How a run waits between opening a PR and merging it
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitStrategy {
+    /// Poll the PR until GitHub has computed whether it can be merged
+    #[default]
+    Poll,
+    /// Sleep a random `pre_merge_wait_secs` and then `post_approve_wait_secs`, like a person would
+    FixedRandom,
+}
+
+/// The `[merge]` config section
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MergeConfig {
+    #[serde(default)]
+    pub wait_strategy: WaitStrategy,
+    /// Seconds between two polls of the PR
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    /// Seconds after which the PR is merged even if it isn't ready yet
+    #[serde(default = "default_max_wait_secs")]
+    pub max_wait_secs: u64,
+}
+
+fn default_poll_interval_secs() -> u64 {
+    5
+}
+
+fn default_max_wait_secs() -> u64 {
+    300
+}
+
+impl Default for MergeConfig {
+    fn default() -> Self {
+        MergeConfig {
+            wait_strategy: WaitStrategy::default(),
+            poll_interval_secs: default_poll_interval_secs(),
+            max_wait_secs: default_max_wait_secs(),
+        }
+    }
+}
+
+impl MergeConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.poll_interval_secs == 0 {
+            return Err("merge.poll_interval_secs must be at least 1".to_string());
+        }
+        if self.max_wait_secs < self.poll_interval_secs {
+            return Err(format!("merge.max_wait_secs ({}) must not be less than merge.poll_interval_secs ({})",
+                self.max_wait_secs, self.poll_interval_secs));
+        }
+        Ok(())
+    }
+}
+
+/// What polling a PR before merging it found
+#[derive(Debug)]
+pub struct MergeWait {
+    pub waited: Duration,
+    /// `mergeable_state` of the last poll, e.g. "clean" or "blocked"
+    pub mergeable_state: Option<String>,
+    /// Required checks that failed, which ends the wait early
+    pub failed_checks: Vec<String>,
+    /// Whether `max_wait_secs` ran out before the PR was ready
+    pub timed_out: bool,
+}
+
+/// Where the required checks of a commit stand
+#[derive(Debug, PartialEq)]
+pub enum ChecksOutcome {
+    Passed,
+    Pending,
+    Failed(Vec<String>),
+}
+
+/// Outcome of the `required` checks of a commit, from its check runs
+/// (`GET /commits/{sha}/check-runs`) and commit statuses (`GET /commits/{sha}/status`).
+/// A check that hasn't reported yet is pending.
+pub fn checks_outcome(required: &[String], check_runs: &Value, statuses: &Value) -> ChecksOutcome {
+    let mut failed = Vec::new();
+    let mut pending = false;
+    for check in required {
+        let run = check_runs["check_runs"].as_array().into_iter().flatten()
+            .find(|run| run["name"].as_str() == Some(check.as_str()));
+        let status = statuses["statuses"].as_array().into_iter().flatten()
+            .find(|status| status["context"].as_str() == Some(check.as_str()));
+        let state = match (run, status) {
+            (Some(run), _) if run["status"].as_str() != Some("completed") => "pending",
+            (Some(run), _) => match run["conclusion"].as_str() {
+                Some("success" | "neutral" | "skipped") => "success",
+                _ => "failure",
+            },
+            (None, Some(status)) => status["state"].as_str().unwrap_or("pending"),
+            (None, None) => "pending",
+        };
+        match state {
+            "success" => {}
+            "pending" => pending = true,
+            _ => failed.push(check.clone()),
+        }
+    }
+    match (failed.is_empty(), pending) {
+        (false, _) => ChecksOutcome::Failed(failed),
+        (true, true) => ChecksOutcome::Pending,
+        (true, false) => ChecksOutcome::Passed,
+    }
+}
+
+fn state_name(state: &MergeableState) -> String {
+    serde_json::to_value(state)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_else(|| format!("{:?}", state).to_lowercase())
+}
+
+impl GitHubBot {
+    /// Poll PR `pr_number` every `poll_interval_secs` until GitHub has computed whether it can
+    /// be merged and the `required_checks` of its head commit passed, for at most `max_wait_secs`.
+    /// Reviews aren't waited for, since the merge step deals with them. A failed poll is retried.
+    pub(crate) async fn wait_until_mergeable(&self, pr_number: u64, required_checks: &[String]) -> Result<MergeWait, Box<dyn std::error::Error>> {
+        let max_wait = Duration::from_secs(self.config.merge.max_wait_secs);
+        let interval = Duration::from_secs(self.config.merge.poll_interval_secs);
+        let started = Instant::now();
+        let mut mergeable_state = None;
+        loop {
+            match self.poll_pr(pr_number, required_checks).await.map_err(|e| e.to_string()) {
+                Ok((state, mergeable, checks)) => {
+                    mergeable_state = state;
+                    if let ChecksOutcome::Failed(failed_checks) = checks {
+                        return Ok(MergeWait { waited: started.elapsed(), mergeable_state, failed_checks, timed_out: false });
+                    }
+                    if mergeable.is_some() && checks == ChecksOutcome::Passed {
+                        return Ok(MergeWait { waited: started.elapsed(), mergeable_state, failed_checks: Vec::new(), timed_out: false });
+                    }
+                    if self.config.debug {
+                        println!("PR #{} isn't ready to merge yet ({})", pr_number, mergeable_state.as_deref().unwrap_or("unknown"));
+                    }
+                }
+                Err(e) => eprintln!("Warning: could not poll PR #{}, trying again: {}", pr_number, e),
+            }
+            let waited = started.elapsed();
+            if waited >= max_wait {
+                return Ok(MergeWait { waited, mergeable_state, failed_checks: Vec::new(), timed_out: true });
+            }
+            time::sleep(interval.min(max_wait - waited)).await;
+        }
+    }
+
+    /// The PR's `mergeable_state` and `mergeable`, and how its head commit's required checks stand
+    async fn poll_pr(&self, pr_number: u64, required_checks: &[String]) -> Result<(Option<String>, Option<bool>, ChecksOutcome), Box<dyn std::error::Error>> {
+        let pr = self.api.typed()
+            .pulls(&self.repo_owner, &self.repo_name)
+            .get(pr_number)
+            .await?;
+        let mergeable_state = pr.mergeable_state.as_ref().map(state_name);
+        if required_checks.is_empty() {
+            return Ok((mergeable_state, pr.mergeable, ChecksOutcome::Passed));
+        }
+        let commit_route = format!("/repos/{}/{}/commits/{}", self.repo_owner, self.repo_name, pr.head.sha);
+        let check_runs: Value = self.api.get(format!("{}/check-runs?per_page=100", commit_route)).await?;
+        let statuses: Value = self.api.get(format!("{}/status?per_page=100", commit_route)).await?;
+        Ok((mergeable_state, pr.mergeable, checks_outcome(required_checks, &check_runs, &statuses)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn required_checks_pass_fail_or_are_still_pending() {
+        let required = vec!["build".to_string(), "lint".to_string()];
+        let check_runs = json!({ "check_runs": [
+            { "name": "build", "status": "completed", "conclusion": "success" },
+            { "name": "optional", "status": "completed", "conclusion": "failure" },
+        ]});
+        let pending_lint = json!({ "statuses": [{ "context": "lint", "state": "pending" }] });
+        assert_eq!(checks_outcome(&required, &check_runs, &pending_lint), ChecksOutcome::Pending);
+        assert_eq!(checks_outcome(&required, &check_runs, &json!({})), ChecksOutcome::Pending);
+
+        let passed_lint = json!({ "statuses": [{ "context": "lint", "state": "success" }] });
+        assert_eq!(checks_outcome(&required, &check_runs, &passed_lint), ChecksOutcome::Passed);
+        let failed_lint = json!({ "statuses": [{ "context": "lint", "state": "error" }] });
+        assert_eq!(checks_outcome(&required, &check_runs, &failed_lint), ChecksOutcome::Failed(vec!["lint".to_string()]));
+        let running = json!({ "check_runs": [{ "name": "build", "status": "in_progress", "conclusion": null }] });
+        assert_eq!(checks_outcome(&required[..1], &running, &json!({})), ChecksOutcome::Pending);
+        assert_eq!(checks_outcome(&[], &json!({}), &json!({})), ChecksOutcome::Passed);
+        assert_eq!(state_name(&MergeableState::Blocked), "blocked");
+
+        let config: MergeConfig = toml::from_str(r#"wait_strategy = "fixed_random""#).unwrap();
+        assert_eq!(config.wait_strategy, WaitStrategy::FixedRandom);
+        assert_eq!((config.poll_interval_secs, config.max_wait_secs), (5, 300));
+        let config = MergeConfig { poll_interval_secs: 10, max_wait_secs: 5, ..MergeConfig::default() };
+        assert!(config.validate().unwrap_err().contains("max_wait_secs"));
+    }
+}
@@ -0,0 +1,204 @@
+use chrono::{DateTime, Utc};
+use rand::{seq::SliceRandom, Rng};
+use serde::{Serialize, Deserialize};
+
+use crate::diffstat::{self, FileStat};
+
+/// How commit messages and PR titles are written
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitStyle {
+    /// "Update 12 files in changes directory"
+    #[default]
+    Plain,
+    /// "chore(changes): tidy change files"
+    Conventional,
+}
+
+/// Conventional commit types with their relative weights
+pub const COMMIT_TYPES: &[(&str, u32)] = &[
+    ("chore", 5),
+    ("docs", 3),
+    ("feat", 2),
+    ("fix", 2),
+    ("refactor", 1),
+];
+
+/// Short descriptions used as the subject of generated messages
+pub const MESSAGE_POOL: &[&str] = &[
+    "add weekly summary",
+    "tidy change files",
+    "update journal",
+    "refresh notes",
+    "record latest progress",
+    "clean up wording",
+    "reorganize entries",
+    "add missing details",
+    "fix typos in notes",
+    "expand daily log",
+    "simplify formatting",
+    "sync change records",
+];
+
+/// Description of the maintenance commit that compacts old change files
+pub const COMPACTION_DESCRIPTION: &str = "tidy up old bot files";
+
+const ADJECTIVES: &[&str] = &[
+    "amber", "brisk", "calm", "clever", "dusty", "eager", "gentle", "hollow",
+    "lively", "mellow", "nimble", "quiet", "rapid", "silver", "sturdy", "wild",
+];
+
+const NOUNS: &[&str] = &[
+    "badger", "canyon", "comet", "falcon", "forest", "harbor", "lantern", "meadow",
+    "otter", "pebble", "river", "summit", "thistle", "valley", "willow", "zephyr",
+];
+
+/// A random `adjective-noun` pair such as `brisk-harbor`
+pub fn word_pair<R: Rng + ?Sized>(rng: &mut R) -> String {
+    format!("{}-{}", ADJECTIVES.choose(rng).unwrap(), NOUNS.choose(rng).unwrap())
+}
+
+/// Build a conventional commit subject such as `docs(notes): update journal`,
+/// with the scope included about half of the time
+pub fn conventional_message<R: Rng + ?Sized>(scope: Option<&str>, rng: &mut R) -> String {
+    let commit_type = COMMIT_TYPES
+        .choose_weighted(rng, |(_, weight)| *weight)
+        .map(|(name, _)| *name)
+        .unwrap_or("chore");
+    let description = MESSAGE_POOL.choose(rng).copied().unwrap_or("update files");
+
+    match scope {
+        Some(scope) if rng.gen_bool(0.5) => format!("{}({}): {}", commit_type, scope, description),
+        _ => format!("{}: {}", commit_type, description),
+    }
+}
+
+/// Whether a commit message looks like one the bot generated, in either style
+pub fn is_bot_message(message: &str) -> bool {
+    let subject = message.lines().next().unwrap_or_default();
+    if subject.starts_with("Update ") && subject.contains(" files in changes directory") {
+        return true;
+    }
+
+    let (prefix, description) = match subject.split_once(": ") {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let commit_type = prefix.split_once('(').map(|(commit_type, _)| commit_type).unwrap_or(prefix);
+    COMMIT_TYPES.iter().any(|(name, _)| *name == commit_type) && (MESSAGE_POOL.contains(&description) || description == COMPACTION_DESCRIPTION)
+}
+
+/// Fill in the placeholders of a `pr_template_file` for a PR opened at `now`. `{file_stats}`
+/// is the table of lines added and removed per file, empty when they aren't known.
+pub fn render_pr_body(template: &str, title: &str, branch: &str, now: DateTime<Utc>, file_stats: &[FileStat]) -> String {
+    let (added, removed) = diffstat::totals(file_stats);
+    let table = if file_stats.is_empty() { String::new() } else { diffstat::summary_table(file_stats) };
+    template
+        .replace("{title}", title)
+        .replace("{branch}", branch)
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+        .replace("{timestamp}", &now.format("%Y-%m-%d %H:%M:%S").to_string())
+        .replace("{files_changed}", &file_stats.len().to_string())
+        .replace("{lines_added}", &added.to_string())
+        .replace("{lines_removed}", &removed.to_string())
+        .replace("{file_stats}", &table)
+}
+
+/// Built-in templates and content strategies as (kind, name, template) rows, for `--list-templates`
+pub fn template_rows() -> Vec<(&'static str, &'static str, String)> {
+    let types: Vec<&str> = COMMIT_TYPES.iter().map(|(name, _)| *name).collect();
+    vec![
+        ("commit message", "plain", "Update {files} files in changes directory[, deleted {deleted}][, renamed {renamed}][, pruned {pruned} old files]".to_string()),
+        ("commit message", "conventional", "{type}[(changes)]: {description}".to_string()),
+        ("commit message", "compaction", format!("Update {{files}} files in changes directory, compacted {{deleted}} into SUMMARY.md / chore(changes): {}", COMPACTION_DESCRIPTION)),
+        ("commit type", "conventional", types.join(", ")),
+        ("description", "conventional", MESSAGE_POOL.join(", ")),
+        ("PR title", "plain", "Bot update {timestamp}".to_string()),
+        ("PR title", "conventional", "{commit subject}".to_string()),
+        ("PR body", "default", "This is an automated PR created by the activity bot. Timestamp: {timestamp}[, table of lines added and removed per file]".to_string()),
+        ("PR body", "pr_template_file", "{title} {branch} {date} {timestamp} {files_changed} {lines_added} {lines_removed} {file_stats}".to_string()),
+        ("merge title", "plain", "Merged bot update PR #{number}".to_string()),
+        ("merge title", "conventional", "{PR title} (#{number})".to_string()),
+        ("content", "timestamped", "Line {n}: Bot update at {timestamp}".to_string()),
+        ("content", "seeded", "Line {n}: Bot update at {random id}".to_string()),
+        ("content", "markdown (.md)", "# {heading}, paragraphs, a rust/bash/python/json code block, a table, ## sections".to_string()),
+        ("content", "json (.json)", "{ \"name\", \"version\", \"items\": [{ \"id\", \"name\", \"status\", \"count\" }, ...] }".to_string()),
+        ("content", "rust (.rs)", "//! {heading}, then documented `pub fn {item}_total_{n}() -> u32` functions".to_string()),
+    ]
+}
+
+/// Format `template_rows` as an aligned table
+pub fn templates_table() -> String {
+    let rows = template_rows();
+    let kind_width = rows.iter().map(|(kind, _, _)| kind.len()).max().unwrap_or(0).max("KIND".len());
+    let name_width = rows.iter().map(|(_, name, _)| name.len()).max().unwrap_or(0).max("NAME".len());
+
+    let mut table = format!("{:<kind_width$}  {:<name_width$}  TEMPLATE\n", "KIND", "NAME");
+    for (kind, name, template) in rows {
+        table.push_str(&format!("{:<kind_width$}  {:<name_width$}  {}\n", kind, name, template));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conventional_messages_have_a_known_type_and_optional_scope() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let message = conventional_message(Some("changes"), &mut rng);
+            let (prefix, description) = message.split_once(": ").unwrap();
+            let commit_type = prefix.strip_suffix("(changes)").unwrap_or(prefix);
+
+            assert!(COMMIT_TYPES.iter().any(|(name, _)| *name == commit_type), "{}", message);
+            assert!(MESSAGE_POOL.contains(&description), "{}", message);
+        }
+    }
+
+    #[test]
+    fn bot_messages_are_recognized_in_both_styles() {
+        let mut rng = rand::thread_rng();
+        assert!(is_bot_message(&conventional_message(Some("changes"), &mut rng)));
+        assert!(is_bot_message("Update 3 files in changes directory, pruned 2 old files"));
+        assert!(!is_bot_message("Fix the login form"));
+        assert!(!is_bot_message("docs: explain the config file"));
+    }
+
+    #[test]
+    fn pr_templates_get_their_placeholders_filled_in() {
+        use chrono::TimeZone;
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 9, 30, 0).unwrap();
+        let stats = vec![FileStat { path: "changes/a.txt".to_string(), renamed_from: None, additions: Some(4), deletions: Some(1) }];
+
+        let body = render_pr_body("## {title}\n{branch} on {date}: +{lines_added} -{lines_removed} in {files_changed}\n{file_stats}", "Bot update", "bot-update-1", now, &stats);
+
+        assert!(body.starts_with("## Bot update\nbot-update-1 on 2024-05-01: +4 -1 in 1\n| File |"), "{}", body);
+        assert!(body.contains("| `changes/a.txt` | 4 | 1 |"), "{}", body);
+        assert_eq!(render_pr_body("{timestamp}{file_stats}", "t", "b", now, &[]), "2024-05-01 09:30:00");
+    }
+
+    #[test]
+    fn every_listed_pr_template_placeholder_is_filled_in() {
+        let (_, _, placeholders) = template_rows().into_iter().find(|(_, name, _)| *name == "pr_template_file").unwrap();
+        let stats = vec![FileStat { path: "changes/a.txt".to_string(), renamed_from: None, additions: Some(4), deletions: Some(1) }];
+
+        let body = render_pr_body(&placeholders, "Bot update", "bot-update-1", Utc::now(), &stats);
+
+        assert!(!body.contains('{') && !body.contains('}'), "{}", body);
+    }
+
+    #[test]
+    fn templates_table_lists_every_row_aligned() {
+        let table = templates_table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), template_rows().len() + 1);
+
+        let column = lines[0].find("TEMPLATE").unwrap();
+        for line in &lines[1..] {
+            assert_eq!(line.as_bytes()[column - 1], b' ', "{}", line);
+            assert_ne!(line.as_bytes()[column], b' ', "{}", line);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use chrono::Utc;
+use http::header::{HeaderMap, HeaderValue, ACCEPT};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use std::fmt;
+
+use crate::{GitHubBot, RunReport};
+
+/// Media type the topics endpoints asked for while they were in preview
+const TOPICS_MEDIA_TYPE: &str = "application/vnd.github.mercy-preview+json";
+/// Most topics GitHub allows on a repository
+const MAX_TOPICS: usize = 20;
+
+/// The `[metadata_activity]` config section: runs that update the repository's description
+/// or topics instead of opening a PR
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MetadataActivityConfig {
+    /// Chance that a run updates the metadata instead of opening a PR
+    #[serde(default)]
+    pub probability: f64,
+    /// Descriptions the repository's description is rotated among; left alone when empty
+    #[serde(default)]
+    pub descriptions: Vec<String>,
+    /// Topics a random subset of which the repository carries; topics outside this list are kept
+    #[serde(default)]
+    pub topics: Vec<String>,
+}
+
+impl MetadataActivityConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.probability) {
+            return Err("metadata_activity.probability must be between 0 and 1".to_string());
+        }
+        if self.descriptions.is_empty() && self.topics.is_empty() {
+            return Err("metadata_activity needs descriptions or topics to rotate".to_string());
+        }
+        if self.topics.len() > MAX_TOPICS {
+            return Err(format!("metadata_activity.topics can have at most {} topics", MAX_TOPICS));
+        }
+        for topic in &self.topics {
+            let valid = !topic.is_empty()
+                && topic.len() <= 50
+                && !topic.starts_with('-')
+                && topic.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if !valid {
+                return Err(format!(
+                    "metadata_activity topic \"{}\" must be up to 50 lowercase letters, digits and hyphens, not starting with a hyphen",
+                    topic
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A metadata field a `metadata_activity` run changed, with its old and new value
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataChange {
+    pub field: &'static str,
+    pub before: String,
+    pub after: String,
+}
+
+impl fmt::Display for MetadataChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} \"{}\" -> \"{}\"", self.field, self.before, self.after)
+    }
+}
+
+/// One of `descriptions` other than `current`, if there is one
+pub fn pick_description(current: Option<&str>, descriptions: &[String], rng: &mut StdRng) -> Option<String> {
+    let candidates: Vec<&String> = descriptions.iter().filter(|description| Some(description.as_str()) != current).collect();
+    candidates.choose(rng).map(|description| description.to_string())
+}
+
+/// `current` with its topics from `pool` replaced by a different random subset of `pool`.
+/// Topics not in `pool` are never removed. `None` when no other subset fits.
+pub fn rotate_topics(current: &[String], pool: &[String], rng: &mut StdRng) -> Option<Vec<String>> {
+    let unmanaged: Vec<String> = current.iter().filter(|topic| !pool.contains(topic)).cloned().collect();
+    let room = MAX_TOPICS.saturating_sub(unmanaged.len()).min(pool.len());
+    if room == 0 {
+        return None;
+    }
+    let mut managed: Vec<String> = current.iter().filter(|topic| pool.contains(topic)).cloned().collect();
+    managed.sort();
+    // A few tries, since a small pool often draws the subset it already has
+    for _ in 0..5 {
+        let count = rng.gen_range(1..=room);
+        let mut picked: Vec<String> = pool.choose_multiple(rng, count).cloned().collect();
+        picked.sort();
+        if picked != managed {
+            return Some(unmanaged.into_iter().chain(picked).collect());
+        }
+    }
+    None
+}
+
+fn topics_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static(TOPICS_MEDIA_TYPE));
+    headers
+}
+
+impl GitHubBot {
+    /// Run that changes the repository's description or its topics, whichever of the two
+    /// `metadata` rotates, picking one at random when it rotates both
+    pub(crate) async fn run_metadata(&self, run_id: &str, metadata: &MetadataActivityConfig, rng: &mut StdRng) -> Result<RunReport, Box<dyn std::error::Error>> {
+        let mut report = RunReport::new(run_id.to_string());
+        println!("Starting metadata run at {}", report.started_at);
+
+        let update_topics = match (metadata.descriptions.is_empty(), metadata.topics.is_empty()) {
+            (true, _) => true,
+            (false, true) => false,
+            (false, false) => rng.gen_bool(0.5),
+        };
+        let change = if update_topics {
+            self.rotate_repo_topics(&metadata.topics, rng).await?
+        } else {
+            self.rotate_repo_description(&metadata.descriptions, rng).await?
+        };
+        match change {
+            Some(change) => {
+                println!("Changed the {} of {}", change, self.config.repo);
+                report.metadata_change = Some(change);
+            }
+            None => report.skipped = Some("no other description or topics to rotate to".to_string()),
+        }
+
+        report.finished_at = Some(Utc::now());
+        Ok(report)
+    }
+
+    async fn rotate_repo_description(&self, descriptions: &[String], rng: &mut StdRng) -> Result<Option<MetadataChange>, Box<dyn std::error::Error>> {
+        let route = format!("/repos/{}/{}", self.repo_owner, self.repo_name);
+        let repo: Value = self.api.get(&route).await?;
+        let before = repo["description"].as_str().unwrap_or_default().to_string();
+        let Some(after) = pick_description(Some(&before), descriptions, rng) else {
+            return Ok(None);
+        };
+        // Only the description is sent, so every other setting stays as it is
+        let _: Value = self.api.patch(&route, &json!({ "description": after })).await
+            .map_err(|e| format!("Could not update the description of {}: {}", self.config.repo, e))?;
+        Ok(Some(MetadataChange { field: "description", before, after }))
+    }
+
+    async fn rotate_repo_topics(&self, pool: &[String], rng: &mut StdRng) -> Result<Option<MetadataChange>, Box<dyn std::error::Error>> {
+        let route = format!("/repos/{}/{}/topics", self.repo_owner, self.repo_name);
+        let response = self.api.get_response_with_headers(&route, topics_headers()).await?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        if !status.is_success() {
+            return Err(format!("Could not read the topics of {} ({}): {}", self.config.repo, status, String::from_utf8_lossy(&body)).into());
+        }
+        let current: Vec<String> = serde_json::from_slice::<Value>(&body)?["names"]
+            .as_array()
+            .map(|names| names.iter().filter_map(|name| name.as_str().map(str::to_string)).collect())
+            .unwrap_or_default();
+        let Some(topics) = rotate_topics(&current, pool, rng) else {
+            return Ok(None);
+        };
+
+        let response = self.api.put_response_with_headers(&route, &json!({ "names": topics }), topics_headers()).await?;
+        if !response.status().is_success() {
+            return Err(format!("Could not update the topics of {}: {}", self.config.repo, response.status()).into());
+        }
+        Ok(Some(MetadataChange { field: "topics", before: current.join(", "), after: topics.join(", ") }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn rotation_changes_only_the_managed_values() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let descriptions = strings(&["Notes", "Daily notes"]);
+        assert_eq!(pick_description(Some("Notes"), &descriptions, &mut rng).as_deref(), Some("Daily notes"));
+        assert_eq!(pick_description(Some("Notes"), &strings(&["Notes"]), &mut rng), None);
+
+        let pool = strings(&["notes", "journal", "automation"]);
+        let current = strings(&["rust", "notes"]);
+        for _ in 0..20 {
+            let topics = rotate_topics(&current, &pool, &mut rng).unwrap();
+            assert_eq!(topics[0], "rust");
+            assert!(topics[1..].iter().all(|topic| pool.contains(topic)), "{:?}", topics);
+            assert_ne!(topics, current);
+        }
+        assert_eq!(rotate_topics(&strings(&["notes"]), &strings(&["notes"]), &mut rng), None);
+
+        let config: MetadataActivityConfig = toml::from_str(r#"
+            probability = 0.1
+            topics = ["notes", "Journal"]
+        "#).unwrap();
+        assert!(config.validate().unwrap_err().contains("\"Journal\""));
+        let change = MetadataChange { field: "topics", before: "rust".to_string(), after: "rust, notes".to_string() };
+        assert_eq!(change.to_string(), "topics \"rust\" -> \"rust, notes\"");
+    }
+}
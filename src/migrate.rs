@@ -0,0 +1,186 @@
+use std::{fs, path::Path};
+
+use toml::value::Table;
+
+use crate::Config;
+
+/// Schema version of config files written by this version of the bot
+pub const CONFIG_VERSION: u32 = 3;
+
+/// A change to the config file format, upgrading files to `version`
+struct Migration {
+    version: u32,
+    description: &'static str,
+    apply: fn(&mut Table),
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "rename `seed` to `random_seed`",
+        apply: |table| rename_key(table, "seed", "random_seed"),
+    },
+    Migration {
+        version: 2,
+        description: "move the `api_*_secs` timeouts into `[http]`",
+        apply: |table| {
+            move_into_table(table, "api_timeout_secs", "http", "timeout_secs");
+            move_into_table(table, "api_connect_timeout_secs", "http", "connect_timeout_secs");
+            move_into_table(table, "api_pool_idle_timeout_secs", "http", "pool_idle_timeout_secs");
+        },
+    },
+    Migration {
+        version: 3,
+        description: "replace `review_comments.max_count` with `num_threads`",
+        apply: |table| {
+            let Some(review_comments) = table.get_mut("review_comments").and_then(toml::Value::as_table_mut) else {
+                return;
+            };
+            if let Some(max_count) = review_comments.remove("max_count") {
+                let mut num_threads = Table::new();
+                num_threads.insert("min".to_string(), toml::Value::Integer(1));
+                num_threads.insert("max".to_string(), max_count);
+                review_comments.entry("num_threads".to_string()).or_insert(toml::Value::Table(num_threads));
+            }
+        },
+    },
+];
+
+fn rename_key(table: &mut Table, from: &str, to: &str) {
+    if let Some(value) = table.remove(from) {
+        table.entry(to.to_string()).or_insert(value);
+    }
+}
+
+fn move_into_table(table: &mut Table, from: &str, to_table: &str, to: &str) {
+    let Some(value) = table.remove(from) else {
+        return;
+    };
+    let target = table.entry(to_table.to_string()).or_insert_with(|| toml::Value::Table(Table::new()));
+    if let Some(target) = target.as_table_mut() {
+        target.entry(to.to_string()).or_insert(value);
+    }
+}
+
+/// Upgrade a config file to `CONFIG_VERSION`: apply the migrations it's missing to the top-level
+/// fields and every profile, then add the fields it leaves out with their default values.
+/// Returns the new file and the descriptions of the migrations applied.
+pub fn migrate_config(old: &str) -> Result<(String, Vec<&'static str>), Box<dyn std::error::Error>> {
+    let mut value: toml::Value = toml::from_str(old)?;
+    let table = value.as_table_mut().ok_or("Config file must be a TOML table")?;
+    let version = match table.get("config_version") {
+        Some(version) => version.as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or("config_version must be a non-negative integer")?,
+        None => 0,
+    };
+    if version > CONFIG_VERSION {
+        return Err(format!("The config file is version {}, newer than this bot's {}", version, CONFIG_VERSION).into());
+    }
+
+    let mut profiles = table.remove("profiles");
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version > version) {
+        (migration.apply)(table);
+        for profile in profiles.iter_mut().filter_map(|profiles| profiles.as_table_mut()) {
+            for overrides in profile.iter_mut().filter_map(|(_, overrides)| overrides.as_table_mut()) {
+                (migration.apply)(overrides);
+            }
+        }
+        applied.push(migration.description);
+    }
+
+    // Serializing the parsed config gives every field, with defaults for the missing ones
+    table.insert("config_version".to_string(), toml::Value::Integer(CONFIG_VERSION.into()));
+    let config: Config = toml::Value::Table(table.clone()).try_into()
+        .map_err(|e| format!("The config file can't be migrated: {}", e))?;
+    if let toml::Value::Table(defaults) = toml::Value::try_from(&config)? {
+        for (key, default) in defaults {
+            table.entry(key).or_insert(default);
+        }
+    }
+    if let Some(profiles) = profiles {
+        table.insert("profiles".to_string(), profiles);
+    }
+    Ok((toml::to_string(&value)?, applied))
+}
+
+/// Migrate the config file at `old` and write it to `new`. When both are the same file,
+/// the original is kept next to it with a `.bak` suffix first.
+pub fn migrate_config_file(old: &Path, new: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let old_str = fs::read_to_string(old)?;
+    let (new_str, applied) = migrate_config(&old_str)?;
+    let same_file = old == new || fs::canonicalize(old).ok().is_some_and(|old| fs::canonicalize(new).ok() == Some(old));
+    if same_file {
+        let mut backup = old.as_os_str().to_owned();
+        backup.push(".bak");
+        fs::copy(old, &backup)?;
+        println!("Backed up {} to {}", old.display(), Path::new(&backup).display());
+    }
+    fs::write(new, new_str)?;
+
+    for description in &applied {
+        println!("Applied migration: {}", description);
+    }
+    println!("Wrote {} at config_version {}", new.display(), CONFIG_VERSION);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_CONFIG: &str = r#"
+        username = "octocat"
+        repo = "octocat/activity"
+        repo_path = "."
+        cron_schedule = "0 0 */2 * * *"
+        min_files = 1
+        max_files = 2
+        min_lines = 1
+        max_lines = 5
+        debug = false
+        seed = 7
+        api_timeout_secs = 20
+
+        [review_comments]
+        max_count = 4
+
+        [profiles.dev]
+        seed = 8
+    "#;
+
+    #[test]
+    fn old_configs_get_renamed_keys_defaults_and_a_version() {
+        let (migrated, applied) = migrate_config(OLD_CONFIG).unwrap();
+        assert_eq!(applied, vec![
+            "rename `seed` to `random_seed`",
+            "move the `api_*_secs` timeouts into `[http]`",
+            "replace `review_comments.max_count` with `num_threads`",
+        ]);
+
+        let value: toml::Value = toml::from_str(&migrated).unwrap();
+        assert_eq!(value["config_version"].as_integer(), Some(CONFIG_VERSION.into()));
+        assert_eq!(value["random_seed"].as_integer(), Some(7));
+        assert_eq!(value["profiles"]["dev"]["random_seed"].as_integer(), Some(8));
+        assert!(value.get("seed").is_none());
+        assert_eq!(value["http"]["timeout_secs"].as_integer(), Some(20));
+        assert_eq!(value["http"]["retries"].as_integer(), Some(3));
+        assert!(value.get("api_timeout_secs").is_none());
+        assert_eq!(value["review_comments"]["num_threads"]["max"].as_integer(), Some(4));
+        assert!(value["review_comments"].get("max_count").is_none());
+        assert_eq!(value["branch_prefix"].as_str(), Some("bot-update-"));
+
+        let config = Config::parse(&migrated, Some("dev")).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.random_seed, Some(8));
+
+        // Migrating again changes nothing
+        let (again, applied) = migrate_config(&migrated).unwrap();
+        assert!(applied.is_empty());
+        assert_eq!(again, migrated);
+
+        let missing = OLD_CONFIG.replace("username = \"octocat\"", "");
+        assert!(migrate_config(&missing).unwrap_err().to_string().contains("username"));
+    }
+}
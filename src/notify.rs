@@ -0,0 +1,121 @@
+use http::header::CONTENT_TYPE;
+use hyper::{Body, Request};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Discord rejects messages longer than this
+const DISCORD_MAX_CONTENT: usize = 2000;
+
+/// Where notifications are sent, e.g. `{ type = "slack", webhook_url = "https://hooks.slack.com/..." }`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Channel {
+    Slack {
+        webhook_url: String,
+    },
+    Discord {
+        webhook_url: String,
+    },
+    /// Sent through the local `sendmail` binary
+    Email {
+        to: String,
+        #[serde(default = "default_sendmail_path")]
+        sendmail_path: String,
+    },
+}
+
+fn default_sendmail_path() -> String {
+    "/usr/sbin/sendmail".to_string()
+}
+
+impl Channel {
+    fn name(&self) -> &'static str {
+        match self {
+            Channel::Slack { .. } => "Slack",
+            Channel::Discord { .. } => "Discord",
+            Channel::Email { .. } => "email",
+        }
+    }
+
+    /// Hide the webhook URL, which works as a secret for posting to the channel
+    pub fn redact(&mut self) {
+        match self {
+            Channel::Slack { webhook_url } | Channel::Discord { webhook_url } => *webhook_url = "<redacted>".to_string(),
+            Channel::Email { .. } => {}
+        }
+    }
+}
+
+/// Send a message to every channel, returning one error message per channel that failed
+pub async fn send_all(channels: &[Channel], subject: &str, text: &str) -> Vec<String> {
+    let mut failures = Vec::new();
+    for channel in channels {
+        if let Err(e) = send(channel, subject, text).await {
+            failures.push(format!("Could not send {} notification: {}", channel.name(), e));
+        }
+    }
+    failures
+}
+
+pub async fn send(channel: &Channel, subject: &str, text: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match channel {
+        Channel::Slack { webhook_url } => {
+            post_json(webhook_url, json!({ "text": format!("*{}*\n{}", subject, text) })).await
+        }
+        Channel::Discord { webhook_url } => {
+            let content: String = format!("**{}**\n{}", subject, text)
+                .chars()
+                .take(DISCORD_MAX_CONTENT)
+                .collect();
+            post_json(webhook_url, json!({ "content": content })).await
+        }
+        Channel::Email { to, sendmail_path } => send_email(sendmail_path, to, subject, text).await,
+    }
+}
+
+async fn post_json(url: &str, payload: serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client = hyper::Client::builder().build::<_, Body>(connector);
+
+    let request = Request::post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(payload.to_string()))?;
+    let response = client.request(request).await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        return Err(format!("webhook responded with {}: {}", status, String::from_utf8_lossy(&body)).into());
+    }
+
+    Ok(())
+}
+
+async fn send_email(sendmail_path: &str, to: &str, subject: &str, text: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new(sendmail_path)
+        .arg("-t")
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("failed to start {}: {}", sendmail_path, e))?;
+
+    let message = format!("To: {}\nSubject: {}\nContent-Type: text/plain; charset=utf-8\n\n{}\n", to, subject, text);
+    // Dropping stdin closes it, so sendmail sees the end of the message
+    let mut stdin = child.stdin.take().ok_or("sendmail has no stdin")?;
+    stdin.write_all(message.as_bytes()).await?;
+    drop(stdin);
+
+    let status = child.wait().await?;
+    if !status.success() {
+        return Err(format!("{} exited with {}", sendmail_path, status).into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Stderr, Write};
+
+use crate::{GitHubBot, RunReport};
+
+/// Stdout for machine-readable output only, while the bot's log lines go to stderr
+pub struct MachineOutput {
+    out: File,
+    _logs: gag::Redirect<Stderr>,
+}
+
+impl MachineOutput {
+    /// Keep a handle on the real stdout, then send everything printed to stdout to stderr
+    pub fn new() -> io::Result<Self> {
+        let out = clone_stdout()?;
+        let logs = gag::Redirect::stdout(io::stderr())?;
+        Ok(MachineOutput { out, _logs: logs })
+    }
+
+    /// Write `value` as one line of JSON
+    pub fn send(&mut self, value: &impl Serialize) -> io::Result<()> {
+        let line = serde_json::to_string(value)?;
+        writeln!(self.out, "{}", line)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(unix)]
+fn clone_stdout() -> io::Result<File> {
+    use std::os::fd::AsFd;
+    Ok(File::from(io::stdout().as_fd().try_clone_to_owned()?))
+}
+
+#[cfg(windows)]
+fn clone_stdout() -> io::Result<File> {
+    use std::os::windows::io::AsHandle;
+    Ok(File::from(io::stdout().as_handle().try_clone_to_owned()?))
+}
+
+/// The branch and PR a failed run left behind, so the caller can find them
+#[derive(Debug, Default)]
+pub struct LeftBehind {
+    pub branch_name: Option<String>,
+    pub pr_number: Option<u64>,
+    pub pr_url: Option<String>,
+}
+
+impl GitHubBot {
+    /// What the interrupted run in the state file left behind
+    pub(crate) async fn left_behind(&self) -> LeftBehind {
+        let Some(run) = self.load_state().ok().and_then(|state| state.current_run) else {
+            return LeftBehind::default();
+        };
+        let pr_url = match run.pr_number {
+            Some(pr_number) => self.api.typed()
+                .pulls(&self.repo_owner, &self.repo_name)
+                .get(pr_number)
+                .await
+                .ok()
+                .and_then(|pr| pr.html_url)
+                .map(|url| url.to_string()),
+            None => None,
+        };
+        LeftBehind { branch_name: Some(run.branch_name), pr_number: run.pr_number, pr_url }
+    }
+}
+
+/// Outcome of a `--run-now` run, printed as one JSON object by `--output json`
+#[derive(Debug, Serialize)]
+pub struct RunResult {
+    pub success: bool,
+    pub branch_name: Option<String>,
+    pub pr_number: Option<u64>,
+    pub pr_url: Option<String>,
+    pub files_changed: Option<usize>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub error: Option<String>,
+}
+
+impl RunResult {
+    /// Result of a run that started at `started_at`, whether it succeeded or failed. A failed
+    /// run names what it `left_behind`.
+    pub fn new(started_at: DateTime<Utc>, outcome: &Result<RunReport, Box<dyn std::error::Error>>, left_behind: LeftBehind) -> Self {
+        match outcome {
+            Ok(report) => RunResult {
+                success: true,
+                branch_name: report.branch_name.clone(),
+                pr_number: report.pr_number,
+                pr_url: report.pr_url.clone(),
+                files_changed: report.files_changed,
+                started_at: report.started_at,
+                finished_at: report.finished_at.unwrap_or_else(Utc::now),
+                error: None,
+            },
+            Err(e) => RunResult {
+                success: false,
+                branch_name: left_behind.branch_name,
+                pr_number: left_behind.pr_number,
+                pr_url: left_behind.pr_url,
+                files_changed: None,
+                started_at,
+                finished_at: Utc::now(),
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn run_results_are_flat_json() {
+        let mut report = RunReport::new("run-1".to_string());
+        report.branch_name = Some("bot-update-1".to_string());
+        report.pr_number = Some(42);
+        report.files_changed = Some(2);
+        let value = serde_json::to_value(RunResult::new(Utc::now(), &Ok(report), LeftBehind::default())).unwrap();
+        assert_eq!(value["success"], true);
+        assert_eq!(value["pr_number"], 42);
+        assert_eq!(value["pr_url"], json!(null));
+        assert_eq!(value["error"], json!(null));
+
+        let left_behind = LeftBehind { branch_name: Some("bot-update-1".to_string()), pr_number: Some(7), pr_url: None };
+        let failed = RunResult::new(Utc::now(), &Err("merge failed".into()), left_behind);
+        let value = serde_json::to_value(failed).unwrap();
+        assert_eq!(value["success"], false);
+        assert_eq!(value["error"], "merge failed");
+        assert_eq!(value["pr_number"], 7);
+    }
+}
@@ -0,0 +1,119 @@
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::fmt;
+
+use crate::{messages, GitHubBot};
+
+/// A push by `username` that the bot didn't make, found by the `quiet_after_human_hours` check
+#[derive(Debug, Clone, PartialEq)]
+pub struct HumanActivity {
+    pub at: DateTime<Utc>,
+    /// Repository the push went to
+    pub repo: String,
+    pub branch: String,
+}
+
+impl fmt::Display for HumanActivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "push to {} of {} at {}", self.branch, self.repo, self.at.format("%Y-%m-%d %H:%M:%S UTC"))
+    }
+}
+
+/// What the bot would have written: a commit by one of its identities, or one of its commit
+/// or merge messages. Conventional merge titles are the PR title followed by " (#N)".
+pub fn is_bot_commit(commit: &Value, bot_emails: &[&str]) -> bool {
+    let email = commit["author"]["email"].as_str().unwrap_or_default();
+    if bot_emails.iter().any(|bot_email| bot_email.eq_ignore_ascii_case(email)) {
+        return true;
+    }
+    let message = commit["message"].as_str().unwrap_or_default();
+    let subject = message.lines().next().unwrap_or_default();
+    let without_pr_number = match subject.rsplit_once(" (#") {
+        Some((title, number)) if number.trim_end_matches(')').parse::<u64>().is_ok() => title,
+        _ => subject,
+    };
+    subject.starts_with("Merged bot update PR #") || messages::is_bot_message(message) || messages::is_bot_message(without_pr_number)
+}
+
+/// The push `event` as human activity by `username` after `since`, unless it went to one of the
+/// bot's branches or all of its commits are the bot's
+pub fn human_push(event: &Value, username: &str, branch_prefix: &str, bot_emails: &[&str], since: DateTime<Utc>) -> Option<HumanActivity> {
+    if event["type"] != "PushEvent" {
+        return None;
+    }
+    let login = event["actor"]["login"].as_str()?;
+    if !login.eq_ignore_ascii_case(username) {
+        return None;
+    }
+    let at = DateTime::parse_from_rfc3339(event["created_at"].as_str()?).ok()?.with_timezone(&Utc);
+    let branch = event["payload"]["ref"].as_str()?.trim_start_matches("refs/heads/");
+    if at <= since || branch.starts_with(branch_prefix) {
+        return None;
+    }
+    let commits = event["payload"]["commits"].as_array()?;
+    if commits.is_empty() || commits.iter().all(|commit| is_bot_commit(commit, bot_emails)) {
+        return None;
+    }
+    Some(HumanActivity {
+        at,
+        repo: event["repo"]["name"].as_str().unwrap_or_default().to_string(),
+        branch: branch.to_string(),
+    })
+}
+
+impl GitHubBot {
+    /// The latest push by `username` to `repo` in the last `quiet_after_human_hours` that the
+    /// bot didn't make, also looking at the user's other repositories with `quiet_check_user_events`
+    pub(crate) async fn recent_human_activity(&self, hours: u64) -> Result<Option<HumanActivity>, Box<dyn std::error::Error>> {
+        let since = Utc::now() - chrono::Duration::hours(hours as i64);
+        let mut routes = vec![format!("/repos/{}/{}/events?per_page=100", self.repo_owner, self.repo_name)];
+        if self.config.quiet_check_user_events {
+            routes.push(format!("/users/{}/events?per_page=100", self.config.username));
+        }
+        let mut bot_emails: Vec<&str> = self.config.commit_authors.iter().map(|author| author.email.as_str()).collect();
+        bot_emails.extend(self.config.author_email.as_deref());
+        let branch_prefix = self.bot_branch_prefix();
+
+        let mut latest: Option<HumanActivity> = None;
+        for route in routes {
+            let events: Vec<Value> = self.api.get(route).await?;
+            for activity in events.iter().filter_map(|event| human_push(event, &self.config.username, &branch_prefix, &bot_emails, since)) {
+                if latest.as_ref().is_none_or(|latest| activity.at > latest.at) {
+                    latest = Some(activity);
+                }
+            }
+        }
+        Ok(latest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    #[test]
+    fn only_pushes_the_bot_did_not_make_count() {
+        let since = Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap();
+        let push = |branch: &str, message: &str, email: &str| json!({
+            "type": "PushEvent",
+            "actor": { "login": "Octocat" },
+            "repo": { "name": "octocat/activity" },
+            "created_at": "2024-05-01T09:30:00Z",
+            "payload": { "ref": format!("refs/heads/{}", branch), "commits": [{ "message": message, "author": { "email": email } }] },
+        });
+        let check = |event: &Value| human_push(event, "octocat", "bot-update-", &["bot@example.com"], since);
+
+        let human = check(&push("master", "Fix the login form", "me@example.com")).unwrap();
+        assert_eq!(human.branch, "master");
+        assert_eq!(human.to_string(), "push to master of octocat/activity at 2024-05-01 09:30:00 UTC");
+
+        assert!(check(&push("bot-update-1714555800", "Fix the login form", "me@example.com")).is_none());
+        assert!(check(&push("master", "Fix the login form", "bot@example.com")).is_none());
+        assert!(check(&push("master", "Merged bot update PR #12", "me@example.com")).is_none());
+        assert!(check(&push("master", "docs(changes): update journal (#12)", "me@example.com")).is_none());
+        let old = Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 0).unwrap();
+        assert!(human_push(&push("master", "Fix the login form", "me@example.com"), "octocat", "bot-update-", &[], old).is_none());
+    }
+}
@@ -0,0 +1,137 @@
+use chrono::NaiveDate;
+
+/// Path of the README, relative to the repository root
+pub const README_PATH: &str = "README.md";
+
+pub const START_MARKER: &str = "<!-- bot:last-updated:start -->";
+pub const END_MARKER: &str = "<!-- bot:last-updated:end -->";
+
+/// Rewrite the "last updated" line between the bot's markers in `content`, appending the
+/// markers at the end of the file if there are none. Only the text between the markers
+/// changes; the run counter continues from the one already there.
+pub fn update_last_updated(content: &str, date: NaiveDate) -> String {
+    let eol = if content.contains("\r\n") { "\r\n" } else { "\n" };
+
+    let start = match content.find(START_MARKER) {
+        Some(start) => start,
+        None => {
+            let mut updated = content.to_string();
+            if !updated.is_empty() {
+                if !updated.ends_with('\n') {
+                    updated.push_str(eol);
+                }
+                updated.push_str(eol);
+            }
+            updated.push_str(&format!("{}{}{}{}{}{}", START_MARKER, eol, last_updated_line(date, 1), eol, END_MARKER, eol));
+            return updated;
+        }
+    };
+    let after_start = start + START_MARKER.len();
+
+    match content[after_start..].find(END_MARKER).map(|end| after_start + end) {
+        Some(end) => {
+            let run = previous_run(&content[after_start..end]) + 1;
+            // Keep the end marker's indentation and the start marker line's own ending
+            let region_end = content[after_start..end].rfind('\n').map(|i| after_start + i + 1).unwrap_or(end);
+            let region_start = content[after_start..region_end].find('\n').map(|i| after_start + i + 1);
+            let (head, line) = match region_start {
+                Some(region_start) => (&content[..region_start], format!("{}{}", last_updated_line(date, run), eol)),
+                // Both markers on one line
+                None => (&content[..after_start], format!("{}{}{}", eol, last_updated_line(date, run), eol)),
+            };
+            format!("{}{}{}", head, line, &content[region_end..])
+        }
+        None => {
+            // A lone start marker: close the region right after it
+            let (head, tail) = match content[after_start..].find('\n') {
+                Some(newline) => content.split_at(after_start + newline + 1),
+                None => (content, ""),
+            };
+            let mut updated = head.to_string();
+            if !head.ends_with('\n') {
+                updated.push_str(eol);
+            }
+            updated.push_str(&format!("{}{}{}", last_updated_line(date, 1), eol, END_MARKER));
+            if !tail.is_empty() || head.ends_with('\n') {
+                updated.push_str(eol);
+            }
+            updated.push_str(tail);
+            updated
+        }
+    }
+}
+
+fn last_updated_line(date: NaiveDate, run: u64) -> String {
+    format!("_Last updated: {} (run {})_", date.format("%Y-%m-%d"), run)
+}
+
+/// The counter in a region written by `last_updated_line`, or 0
+fn previous_run(region: &str) -> u64 {
+    region
+        .split("(run ")
+        .nth(1)
+        .and_then(|rest| rest.split(')').next())
+        .and_then(|run| run.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+    }
+
+    #[test]
+    fn markers_are_appended_and_then_updated_in_place() {
+        let readme = "# Project\n\nReal content.\n";
+        let first = update_last_updated(readme, date());
+        assert_eq!(
+            first,
+            "# Project\n\nReal content.\n\n<!-- bot:last-updated:start -->\n_Last updated: 2024-05-01 (run 1)_\n<!-- bot:last-updated:end -->\n"
+        );
+
+        let edited = first.replace("Real content.", "Edited by hand.") + "\n## More\n";
+        let second = update_last_updated(&edited, NaiveDate::from_ymd_opt(2024, 5, 2).unwrap());
+        assert_eq!(second, edited.replace("2024-05-01 (run 1)", "2024-05-02 (run 2)"));
+    }
+
+    #[test]
+    fn only_the_region_between_markers_changes() {
+        // End marker at EOF without a trailing newline
+        let readme = "Intro\n  <!-- bot:last-updated:start -->\nold text\nmore old text\n  <!-- bot:last-updated:end -->";
+        assert_eq!(
+            update_last_updated(readme, date()),
+            "Intro\n  <!-- bot:last-updated:start -->\n_Last updated: 2024-05-01 (run 1)_\n  <!-- bot:last-updated:end -->"
+        );
+
+        // Missing end marker: nothing after the start marker is replaced
+        let readme = "Intro\n<!-- bot:last-updated:start -->\nKeep me\n";
+        assert_eq!(
+            update_last_updated(readme, date()),
+            "Intro\n<!-- bot:last-updated:start -->\n_Last updated: 2024-05-01 (run 1)_\n<!-- bot:last-updated:end -->\nKeep me\n"
+        );
+        assert_eq!(
+            update_last_updated("Intro\n<!-- bot:last-updated:start -->", date()),
+            "Intro\n<!-- bot:last-updated:start -->\n_Last updated: 2024-05-01 (run 1)_\n<!-- bot:last-updated:end -->"
+        );
+
+        // Both markers on one line
+        assert_eq!(
+            update_last_updated("<!-- bot:last-updated:start --><!-- bot:last-updated:end -->\n", date()),
+            "<!-- bot:last-updated:start -->\n_Last updated: 2024-05-01 (run 1)_\n<!-- bot:last-updated:end -->\n"
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings_are_kept() {
+        let readme = "# Project\r\nContent\r\n";
+        let first = update_last_updated(readme, date());
+        assert!(first.starts_with(readme), "{:?}", first);
+        assert_eq!(first.matches('\n').count(), first.matches("\r\n").count(), "{:?}", first);
+
+        let second = update_last_updated(&first, date());
+        assert_eq!(second, first.replace("(run 1)", "(run 2)"));
+    }
+}
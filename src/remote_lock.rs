@@ -0,0 +1,203 @@
+use chrono::{DateTime, Duration, Utc};
+use http::StatusCode;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use crate::{GhApi, GitHubBot};
+
+/// Namespace of the lock refs, outside of branches and tags so GitHub doesn't list them
+const LOCK_REF_NAMESPACE: &str = "bot-locks";
+
+/// Times creating the lock ref is tried while GitHub refuses it although no lock exists
+pub const MAX_LOCK_ATTEMPTS: u32 = 5;
+
+/// How runs on several machines keep from overlapping
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DistributedLock {
+    /// A `refs/bot-locks/<repo>` ref in the target repository; GitHub refusing to create a ref
+    /// that already exists decides which machine runs
+    GithubRef,
+}
+
+/// Content of the blob the lock ref points to
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct LockInfo {
+    pub machine: String,
+    pub acquired_at: DateTime<Utc>,
+}
+
+impl LockInfo {
+    /// Whether the machine holding the lock has had it longer than `ttl_secs`, e.g. because it
+    /// crashed before releasing it
+    pub fn is_stale(&self, now: DateTime<Utc>, ttl_secs: u64) -> bool {
+        now - self.acquired_at > Duration::seconds(ttl_secs as i64)
+    }
+}
+
+/// Host name and process id of this bot, naming the lock holder
+pub fn machine_id() -> String {
+    let host = std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    format!("{}:{}", host, std::process::id())
+}
+
+/// Outcome of trying to take the lock
+pub enum LockAttempt {
+    Acquired(Box<RemoteLockGuard>),
+    /// Another machine holds the lock, with a description of the holder
+    Held(String),
+}
+
+/// The lock ref this machine created. Released with `release`, or in the background when
+/// dropped, e.g. when a run fails before reaching the release.
+pub struct RemoteLockGuard {
+    api: GhApi,
+    repo_route: String,
+    ref_name: String,
+    blob_sha: String,
+    released: bool,
+}
+
+impl RemoteLockGuard {
+    pub async fn release(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.released = true;
+        release_ref(&self.api, &self.repo_route, &self.ref_name, &self.blob_sha).await
+    }
+}
+
+impl Drop for RemoteLockGuard {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            eprintln!("Warning: could not release the run lock {}, it expires after lock_ttl_secs", self.ref_name);
+            return;
+        };
+        let (api, repo_route) = (self.api.clone(), self.repo_route.clone());
+        let (ref_name, blob_sha) = (self.ref_name.clone(), self.blob_sha.clone());
+        runtime.spawn(async move {
+            if let Err(e) = release_ref(&api, &repo_route, &ref_name, &blob_sha).await {
+                eprintln!("Warning: could not release the run lock {}: {}", ref_name, e);
+            }
+        });
+    }
+}
+
+/// Delete the lock ref, unless another machine has since replaced it with its own lock
+async fn release_ref(api: &GhApi, repo_route: &str, ref_name: &str, blob_sha: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if lock_target(api, repo_route, ref_name).await?.as_deref() != Some(blob_sha) {
+        return Ok(());
+    }
+    let route = format!("{}/git/refs/{}", repo_route, ref_name);
+    let status = api.delete_response(&route).await?.status();
+    if !status.is_success() && status != StatusCode::UNPROCESSABLE_ENTITY {
+        return Err(format!("GitHub returned {} when deleting {}", status, ref_name).into());
+    }
+    Ok(())
+}
+
+/// Sha of the blob the lock ref points to, or `None` if there is no lock
+async fn lock_target(api: &GhApi, repo_route: &str, ref_name: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let route = format!("{}/git/ref/{}", repo_route, ref_name);
+    let response = api.get_response(&route).await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(format!("GitHub returned {} when reading {}", response.status(), ref_name).into());
+    }
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    let lock: Value = serde_json::from_slice(&body)?;
+    Ok(lock["object"]["sha"].as_str().map(String::from))
+}
+
+impl GitHubBot {
+    /// Take the run lock for the target repository. If the lock is stale it's deleted and
+    /// taken over; when two machines race for it, only one of them gets to create the ref.
+    pub(crate) async fn acquire_remote_lock(&self) -> Result<LockAttempt, Box<dyn std::error::Error>> {
+        let repo_route = format!("/repos/{}/{}", self.head_owner(), self.repo_name);
+        let ref_name = format!("{}/{}", LOCK_REF_NAMESPACE, self.repo_name);
+        let info = LockInfo { machine: machine_id(), acquired_at: Utc::now() };
+        let blob: Value = self.api
+            .post(
+                format!("{}/git/blobs", repo_route),
+                &json!({ "content": serde_json::to_string(&info)?, "encoding": "utf-8" }),
+            )
+            .await?;
+        let blob_sha = blob["sha"].as_str().ok_or("Created lock blob has no sha")?.to_string();
+
+        let mut took_over = false;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let body = json!({ "ref": format!("refs/{}", ref_name), "sha": blob_sha });
+            let status = self.api.post_response(&format!("{}/git/refs", repo_route), &body).await?.status();
+            if status.is_success() {
+                if self.config.debug {
+                    println!("Took the run lock refs/{} as {}", ref_name, info.machine);
+                }
+                let api = self.api.clone();
+                return Ok(LockAttempt::Acquired(Box::new(RemoteLockGuard { api, repo_route, ref_name, blob_sha, released: false })));
+            }
+            if status != StatusCode::UNPROCESSABLE_ENTITY {
+                return Err(format!("GitHub returned {} when creating the run lock", status).into());
+            }
+
+            // The ref exists: find out who holds it, unless it was released in the meantime
+            let Some(holder_sha) = lock_target(&self.api, &repo_route, &ref_name).await? else {
+                // A 422 that isn't about an existing ref would otherwise be retried forever
+                if attempts >= MAX_LOCK_ATTEMPTS {
+                    return Err(format!("GitHub returned {} {} times when creating the run lock, but no lock exists", status, attempts).into());
+                }
+                continue;
+            };
+            let holder = self.lock_info(&repo_route, &holder_sha).await;
+            match holder {
+                Some(holder) if took_over || !holder.is_stale(Utc::now(), self.config.lock_ttl_secs) => {
+                    return Ok(LockAttempt::Held(format!("{} has held the run lock since {}", holder.machine, holder.acquired_at)));
+                }
+                _ if took_over => return Ok(LockAttempt::Held("another machine took over the stale run lock".to_string())),
+                // Stale, or left behind in a shape this bot can't read
+                _ => {
+                    eprintln!("Warning: taking over the stale run lock refs/{}", ref_name);
+                    release_ref(&self.api, &repo_route, &ref_name, &holder_sha).await?;
+                    took_over = true;
+                }
+            }
+        }
+    }
+
+    /// The holder recorded in the lock blob, or `None` if it can't be read
+    async fn lock_info(&self, repo_route: &str, blob_sha: &str) -> Option<LockInfo> {
+        let mut headers = http::header::HeaderMap::new();
+        headers.insert(http::header::ACCEPT, http::HeaderValue::from_static("application/vnd.github.raw"));
+        let route = format!("{}/git/blobs/{}", repo_route, blob_sha);
+        let response = self.api.get_response_with_headers(route.as_str(), headers).await.ok()?;
+        let body = hyper::body::to_bytes(response.into_body()).await.ok()?;
+        serde_json::from_slice(&body).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn locks_go_stale_after_the_ttl() {
+        let acquired_at = Utc.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap();
+        let info: LockInfo = serde_json::from_str(&format!(
+            r#"{{"machine":"box-a:42","acquired_at":"{}"}}"#,
+            acquired_at.to_rfc3339(),
+        )).unwrap();
+        assert_eq!(info.machine, "box-a:42");
+        assert!(!info.is_stale(acquired_at + Duration::seconds(600), 900));
+        assert!(info.is_stale(acquired_at + Duration::seconds(901), 900));
+    }
+}
@@ -0,0 +1,149 @@
+use octocrab::models::pulls::PullRequest;
+use octocrab::params::{pulls::State as PullState, State};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::{GitHubBot, RemoteBranchDeletion, CHANGES_DIR, PLAIN_TITLE_PREFIX};
+
+/// What `--reset` is about to remove
+#[derive(Debug, Default)]
+pub struct ResetPlan {
+    /// Remote branches starting with the bot's branch prefix
+    pub branches: Vec<String>,
+    /// Open bot PRs, with their titles
+    pub prs: Vec<(u64, String)>,
+    /// Whether the `changes/` directory of the clone is deleted too
+    pub delete_changes: bool,
+}
+
+impl fmt::Display for ResetPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Open PRs to close ({}):", self.prs.len())?;
+        for (number, title) in &self.prs {
+            writeln!(f, "  #{} {}", number, title)?;
+        }
+        writeln!(f, "Branches to delete ({}):", self.branches.len())?;
+        for branch in &self.branches {
+            writeln!(f, "  {}", branch)?;
+        }
+        if self.delete_changes {
+            writeln!(f, "The local {}/ directory is deleted too", CHANGES_DIR)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether an open PR is one of the bot's: its head branch has the bot's prefix, or it
+/// has the title of a plain-style bot PR
+pub fn is_bot_pr(title: &str, head_branch: &str, branch_prefix: &str) -> bool {
+    head_branch.starts_with(branch_prefix) || title.starts_with(PLAIN_TITLE_PREFIX)
+}
+
+/// Whether an answer to the confirmation prompt agrees to go ahead
+pub fn confirmed(answer: &str) -> bool {
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+impl GitHubBot {
+    /// List the bot's remote branches and open PRs, which `reset` removes
+    pub(crate) async fn reset_plan(&self, delete_changes: bool) -> Result<ResetPlan, Box<dyn std::error::Error>> {
+        let prefix = self.bot_branch_prefix();
+
+        let first_page = self.api.typed()
+            .repos(self.head_owner(), &self.repo_name)
+            .list_branches()
+            .per_page(100)
+            .send()
+            .await?;
+        let branches = self.api.typed()
+            .all_pages(first_page)
+            .await?
+            .into_iter()
+            .map(|branch| branch.name)
+            .filter(|name| name.starts_with(&prefix))
+            .collect();
+
+        let first_page = self.api.typed()
+            .pulls(&self.repo_owner, &self.repo_name)
+            .list()
+            .state(State::Open)
+            .per_page(100)
+            .send()
+            .await?;
+        let open_prs: Vec<PullRequest> = self.api.typed().all_pages(first_page).await?;
+        let prs = open_prs
+            .into_iter()
+            .filter_map(|pr| {
+                let title = pr.title.unwrap_or_default();
+                is_bot_pr(&title, &pr.head.ref_field, &prefix).then_some((pr.number, title))
+            })
+            .collect();
+
+        Ok(ResetPlan { branches, prs, delete_changes })
+    }
+
+    /// Close the PRs of `plan` without merging them, delete its branches and, if asked, the
+    /// `changes/` directory, and forget the tracked PRs and any interrupted run
+    pub(crate) async fn reset(&self, plan: &ResetPlan) -> Result<(), Box<dyn std::error::Error>> {
+        for (number, _) in &plan.prs {
+            println!("Closing PR #{}", number);
+            self.api.typed()
+                .pulls(&self.repo_owner, &self.repo_name)
+                .update(*number)
+                .state(PullState::Closed)
+                .send()
+                .await?;
+        }
+
+        for branch in &plan.branches {
+            println!("Deleting branch {}", branch);
+            if let RemoteBranchDeletion::AlreadyGone = self.delete_remote_branch(branch).await? {
+                if self.config.debug {
+                    println!("Remote branch {} was already deleted", branch);
+                }
+            }
+        }
+
+        if plan.delete_changes {
+            let changes_dir = Path::new(&self.config.repo_path).join(CHANGES_DIR);
+            if changes_dir.exists() {
+                println!("Deleting {}", changes_dir.display());
+                fs::remove_dir_all(&changes_dir)?;
+            }
+        }
+
+        let mut state = self.load_state()?;
+        state.open_prs.clear();
+        state.current_run = None;
+        self.save_state(&state)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bot_prs_are_recognized_by_branch_or_title() {
+        assert!(is_bot_pr("feat: add notes", "bot-update-1700000000", "bot-update-"));
+        assert!(is_bot_pr("Bot update 2024-05-01 10:00:00", "renamed", "bot-update-"));
+        assert!(!is_bot_pr("Fix the README", "fix-readme", "bot-update-"));
+
+        assert!(confirmed("y\n"));
+        assert!(confirmed(" YES "));
+        assert!(!confirmed(""));
+        assert!(!confirmed("no"));
+
+        let plan = ResetPlan {
+            branches: vec!["bot-update-1".to_string()],
+            prs: vec![(7, "Bot update 2024-05-01 10:00:00".to_string())],
+            delete_changes: true,
+        };
+        assert_eq!(
+            plan.to_string(),
+            "Open PRs to close (1):\n  #7 Bot update 2024-05-01 10:00:00\nBranches to delete (1):\n  bot-update-1\nThe local changes/ directory is deleted too\n"
+        );
+    }
+}
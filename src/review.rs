@@ -0,0 +1,188 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use crate::{CountRange, GitHubBot, RunReport};
+
+/// The `[review_comments]` config section
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReviewCommentsConfig {
+    /// Chance that a PR gets review comments at all
+    #[serde(default = "default_probability")]
+    pub probability: f64,
+    /// Comment texts to pick from
+    #[serde(default = "default_comments")]
+    pub comments: Vec<String>,
+    /// Number of diff hunks that each get a comment thread, e.g. `{ min = 1, max = 3 }`
+    #[serde(default = "default_num_threads")]
+    pub num_threads: CountRange,
+    /// Chance that the bot's own token replies to each comment
+    #[serde(default)]
+    pub reply_probability: f64,
+    /// Reply texts to pick from
+    #[serde(default = "default_replies")]
+    pub replies: Vec<String>,
+}
+
+fn default_probability() -> f64 {
+    0.5
+}
+
+fn default_comments() -> Vec<String> {
+    [
+        "nit: trailing newline",
+        "nit: could this line be shorter?",
+        "Looks good, just double-checking this one.",
+        "Is this still needed?",
+        "nit: wording",
+        "nit: consider renaming this variable",
+        "Can we add a test for this?",
+        "Looks good to me 👍",
+    ]
+    .iter()
+    .map(|comment| comment.to_string())
+    .collect()
+}
+
+fn default_num_threads() -> CountRange {
+    CountRange { min: 1, max: 2 }
+}
+
+fn default_replies() -> Vec<String> {
+    ["Good catch, thanks.", "Yes, keeping it for now.", "Fixed in a follow-up.", "Done."]
+        .iter()
+        .map(|reply| reply.to_string())
+        .collect()
+}
+
+impl ReviewCommentsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, probability) in [("probability", self.probability), ("reply_probability", self.reply_probability)] {
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(format!("review_comments.{} must be between 0 and 1", name));
+            }
+        }
+        if self.num_threads.min > self.num_threads.max {
+            return Err(format!("review_comments.num_threads.min ({}) must not be greater than num_threads.max ({})",
+                self.num_threads.min, self.num_threads.max));
+        }
+        if self.comments.is_empty() {
+            return Err("review_comments.comments must not be empty".to_string());
+        }
+        if self.reply_probability > 0.0 && self.replies.is_empty() {
+            return Err("review_comments.replies must not be empty when reply_probability is set".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Line numbers in the new version of a file that a unified diff `patch` adds, grouped by
+/// hunk. Hunks that only remove lines are left out.
+pub fn added_lines(patch: &str) -> Vec<Vec<u64>> {
+    let mut hunks: Vec<Vec<u64>> = Vec::new();
+    let mut line = 0;
+    for diff_line in patch.lines() {
+        if let Some(header) = diff_line.strip_prefix("@@ ") {
+            hunks.push(Vec::new());
+            // "@@ -1,3 +4,5 @@": the new side starts at line 4
+            line = header
+                .split_whitespace()
+                .find_map(|range| range.strip_prefix('+'))
+                .and_then(|range| range.split(',').next())
+                .and_then(|start| start.parse().ok())
+                .unwrap_or(0);
+        } else if diff_line.starts_with('+') {
+            if let Some(hunk) = hunks.last_mut() {
+                hunk.push(line);
+            }
+            line += 1;
+        } else if !diff_line.starts_with('-') && !diff_line.starts_with('\\') {
+            line += 1;
+        }
+    }
+    hunks.retain(|hunk| !hunk.is_empty());
+    hunks
+}
+
+impl GitHubBot {
+    /// Have the reviewer token start comment threads on `num_threads` of the PR's diff hunks,
+    /// each on a random line the hunk adds, optionally
+    /// answered by the bot's own token. Does nothing without a reviewer token; failures
+    /// are only warnings, so they never hold up the merge.
+    pub(crate) async fn leave_review_comments(&self, pr_number: u64, rng: &mut StdRng, report: &mut RunReport) {
+        let (config, reviewer) = match (&self.config.review_comments, &self.reviewer) {
+            (Some(config), Some(reviewer)) => (config, reviewer),
+            _ => return,
+        };
+        if !rng.gen_bool(config.probability) {
+            return;
+        }
+
+        let route = format!("/repos/{}/{}/pulls/{}", self.repo_owner, self.repo_name, pr_number);
+        let files: Result<Vec<Value>, _> = self.api.get(format!("{}/files", route)).await;
+        let pr: Result<Value, _> = self.api.get(route.as_str()).await;
+        let (files, head_sha) = match (files, pr) {
+            (Ok(files), Ok(pr)) => (files, pr["head"]["sha"].as_str().unwrap_or_default().to_string()),
+            (Err(e), _) | (_, Err(e)) => {
+                report.warn(format!("Could not read the diff of PR #{} for review comments: {}", pr_number, e));
+                return;
+            }
+        };
+
+        let mut hunks: Vec<(String, Vec<u64>)> = files
+            .iter()
+            .filter_map(|file| Some((file["filename"].as_str()?, file["patch"].as_str()?)))
+            .flat_map(|(path, patch)| added_lines(patch).into_iter().map(move |lines| (path.to_string(), lines)))
+            .collect();
+        hunks.shuffle(rng);
+        let count = rng.gen_range(config.num_threads.min..=config.num_threads.max) as usize;
+        let positions: Vec<(String, u64)> = hunks
+            .into_iter()
+            .take(count)
+            .map(|(path, lines)| (path, *lines.choose(rng).unwrap_or(&0)))
+            .collect();
+
+        for (path, line) in positions {
+            let body = config.comments.choose(rng).cloned().unwrap_or_default();
+            let comment: Result<Value, _> = reviewer
+                .post(
+                    format!("{}/comments", route),
+                    &json!({ "body": body, "commit_id": head_sha, "path": path, "line": line, "side": "RIGHT" }),
+                )
+                .await;
+            let comment_id = match comment {
+                Ok(comment) => comment["id"].as_u64(),
+                Err(e) => {
+                    report.warn(format!("Could not comment on {}:{} of PR #{}: {}", path, line, pr_number, e));
+                    continue;
+                }
+            };
+            if self.config.debug {
+                println!("Reviewer commented on {}:{} of PR #{}", path, line, pr_number);
+            }
+
+            if let Some(comment_id) = comment_id.filter(|_| rng.gen_bool(config.reply_probability)) {
+                let reply = config.replies.choose(rng).cloned().unwrap_or_default();
+                let replied: Result<Value, _> = self.api
+                    .post(format!("{}/comments/{}/replies", route, comment_id), &json!({ "body": reply }))
+                    .await;
+                if let Err(e) = replied {
+                    report.warn(format!("Could not reply to review comment {} on PR #{}: {}", comment_id, pr_number, e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn added_lines_follow_hunk_headers() {
+        let patch = "@@ -1,2 +1,3 @@\n context\n-old\n+new\n+newer\n@@ -10,2 +11,2 @@\n context\n+added\n\\ No newline at end of file";
+        assert_eq!(added_lines(patch), vec![vec![2, 3], vec![12]]);
+        assert!(added_lines("@@ -1,2 +1,1 @@\n context\n-old").is_empty());
+        assert!(added_lines("").is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use std::str::FromStr;
+
+/// Names of the fields of a cron expression, in the order the scheduler expects them
+const FIELDS: &str = "second minute hour day-of-month month day-of-week [year]";
+
+/// Parse `cron_schedule`, explaining the expected fields when it has the wrong number of them
+pub fn parse_schedule(expression: &str) -> Result<Schedule, String> {
+    parse_schedule_option("cron_schedule", expression)
+}
+
+/// Parse the cron expression of the config option `option`, e.g. "digest.schedule"
+pub fn parse_schedule_option(option: &str, expression: &str) -> Result<Schedule, String> {
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    if !expression.trim_start().starts_with('@') && !(6..=7).contains(&fields.len()) {
+        let hint = if fields.len() == 5 {
+            format!("; add the seconds in front, e.g. \"0 {}\"", fields.join(" "))
+        } else {
+            String::new()
+        };
+        return Err(format!(
+            "{} \"{}\" has {} fields, but 6 or 7 are expected ({}){}",
+            option, expression, fields.len(), FIELDS, hint
+        ));
+    }
+    Schedule::from_str(expression).map_err(|e| format!("{} \"{}\" is not valid: {}", option, expression, e))
+}
+
+/// The `cron_timezone`, e.g. "Europe/Berlin"
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+    name.parse().map_err(|_| format!("cron_timezone \"{}\" is not a known timezone, e.g. \"Europe/Berlin\"", name))
+}
+
+/// A number field is `*/N`; returns `N`
+fn step(field: &str) -> Option<u32> {
+    field.strip_prefix("*/")?.parse().ok()
+}
+
+fn number(field: &str) -> Option<u32> {
+    field.parse().ok()
+}
+
+/// Plain-English reading of a cron expression, e.g. "every 8 hours at minute 0". Shapes it
+/// doesn't know are described field by field.
+pub fn describe(expression: &str) -> String {
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    let (second, minute, hour, day, month, weekday) = match fields[..] {
+        [second, minute, hour, day, month, weekday, ..] => (second, minute, hour, day, month, weekday),
+        _ => return format!("on schedule {}", expression),
+    };
+
+    let mut description = match (minute, hour) {
+        ("*", "*") => "every minute".to_string(),
+        (minute, "*") if step(minute).is_some() => format!("every {} minutes", step(minute).unwrap_or_default()),
+        (minute, "*") if number(minute).is_some() => format!("every hour at minute {}", minute),
+        (minute, hour) => match (number(minute), number(hour), step(hour)) {
+            (Some(minute), Some(hour), _) => format!("at {:02}:{:02}", hour, minute),
+            (Some(minute), None, Some(1)) => format!("every hour at minute {}", minute),
+            (Some(minute), None, Some(hours)) => format!("every {} hours at minute {}", hours, minute),
+            _ => format!("at minute {} of hour {}", minute, hour),
+        },
+    };
+    if second != "0" {
+        description.push_str(&format!(", second {}", second));
+    }
+    if weekday != "*" && weekday != "?" {
+        description.push_str(&format!(" on {}", weekday));
+    }
+    if day != "*" && day != "?" {
+        description.push_str(&format!(" on day {} of the month", day));
+    }
+    if month != "*" {
+        description.push_str(&format!(" in month {}", month));
+    }
+    description
+}
+
+/// The next `count` times `schedule` fires after `now`, in `timezone`
+pub fn next_occurrences(schedule: &Schedule, timezone: Tz, now: DateTime<Utc>, count: usize) -> Vec<DateTime<Tz>> {
+    schedule.after(&now.with_timezone(&timezone)).take(count).collect()
+}
+
+/// What `cron_schedule` means and when it fires next, as printed at startup and by `--status`
+pub fn summary(expression: &str, timezone: Option<&str>, now: DateTime<Utc>) -> Result<String, String> {
+    let schedule = parse_schedule(expression)?;
+    let timezone = timezone.map(parse_timezone).transpose()?.unwrap_or(Tz::UTC);
+    let mut summary = format!("Schedule \"{}\": {} ({})\nNext runs:", expression, describe(expression), timezone);
+    for next in next_occurrences(&schedule, timezone, now, 5) {
+        summary.push_str(&format!("\n  {}", next.format("%Y-%m-%d %H:%M:%S %Z")));
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn schedules_are_explained_with_their_next_runs() {
+        let error = parse_schedule("0 */8 * * *").unwrap_err();
+        assert!(error.contains("has 5 fields, but 6 or 7 are expected"), "{}", error);
+        assert!(error.contains("\"0 0 */8 * * *\""), "{}", error);
+        assert!(parse_schedule("0 0 */8 * * *").is_ok());
+        assert!(parse_schedule("0 99 * * * *").is_err());
+        assert!(parse_timezone("Mars/Olympus").is_err());
+
+        assert_eq!(describe("0 0 */8 * * *"), "every 8 hours at minute 0");
+        assert_eq!(describe("0 30 9 * * Mon-Fri"), "at 09:30 on Mon-Fri");
+        assert_eq!(describe("0 */15 * * * *"), "every 15 minutes");
+
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 9, 30, 0).unwrap();
+        let summary = summary("0 0 9 * * *", Some("Europe/Berlin"), now).unwrap();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines[0], "Schedule \"0 0 9 * * *\": at 09:00 (Europe/Berlin)");
+        // 09:30 UTC is already past 09:00 in Berlin, so the first run is the next day
+        assert_eq!(lines[2], "  2024-05-02 09:00:00 CEST");
+        assert_eq!(lines.len(), 7);
+    }
+}
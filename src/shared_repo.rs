@@ -0,0 +1,123 @@
+use chrono::{NaiveDate, Utc};
+use http::StatusCode;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::GitHubBot;
+
+/// How far back commits by other people make the repository count as shared
+pub const LOOKBACK_DAYS: i64 = 90;
+
+/// Commits read per request; a shorter page is the last one
+const COMMITS_PER_PAGE: usize = 100;
+
+/// Result of the check for other contributors, made at most once a day
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SharedRepoCheck {
+    pub date: NaiveDate,
+    pub repo: String,
+    /// Logins, or emails of commits without a GitHub account, of the other committers
+    pub others: Vec<String>,
+}
+
+/// Who other than `username` and the bot's own identities authored `commits`, from
+/// `GET /repos/{owner}/{repo}/commits`. GitHub Apps such as dependabot don't count.
+pub fn other_committers(commits: &[Value], username: &str, bot_emails: &[&str]) -> Vec<String> {
+    let mut others: Vec<String> = commits
+        .iter()
+        .filter_map(|commit| {
+            let email = commit["commit"]["author"]["email"].as_str().unwrap_or_default();
+            if bot_emails.iter().any(|bot_email| bot_email.eq_ignore_ascii_case(email)) {
+                return None;
+            }
+            match commit["author"]["login"].as_str() {
+                Some(login) if login.eq_ignore_ascii_case(username) || login.ends_with("[bot]") => None,
+                Some(login) => Some(login.to_string()),
+                None if email.is_empty() => None,
+                None => Some(email.to_string()),
+            }
+        })
+        .collect();
+    others.sort();
+    others.dedup();
+    others
+}
+
+impl GitHubBot {
+    /// Every commit of the last `LOOKBACK_DAYS` days on the default branch; none for an empty repository
+    async fn recent_commits(&self) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        let since = (Utc::now() - chrono::Duration::days(LOOKBACK_DAYS)).format("%Y-%m-%dT%H:%M:%SZ");
+        let mut commits = Vec::new();
+        for page in 1.. {
+            let route = format!("/repos/{}/{}/commits?since={}&per_page={}&page={}", self.repo_owner, self.repo_name, since, COMMITS_PER_PAGE, page);
+            let response = self.api.get_response(&route).await?;
+            let status = response.status();
+            // GitHub answers 409 Conflict for a repository without commits
+            if status == StatusCode::CONFLICT {
+                break;
+            }
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            if !status.is_success() {
+                return Err(format!("Could not list the commits of {} ({}): {}", self.config.repo, status, String::from_utf8_lossy(&body)).into());
+            }
+            let batch: Vec<Value> = serde_json::from_slice(&body)?;
+            let last_page = batch.len() < COMMITS_PER_PAGE;
+            commits.extend(batch);
+            if last_page {
+                break;
+            }
+        }
+        Ok(commits)
+    }
+
+    /// Fail unless only `username` and the bot committed to the repository in the last
+    /// `LOOKBACK_DAYS` days. The answer is kept in the state file for the rest of the day.
+    pub(crate) async fn check_not_shared(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let today = self.config.today();
+        let mut state = self.load_state()?;
+        let others = match state.shared_repo_check.as_ref().filter(|check| check.date == today && check.repo == self.config.repo) {
+            Some(check) => check.others.clone(),
+            None => {
+                let commits = self.recent_commits().await?;
+                let mut bot_emails: Vec<&str> = self.config.commit_authors.iter().map(|author| author.email.as_str()).collect();
+                bot_emails.extend(self.config.author_email.as_deref());
+                let others = other_committers(&commits, &self.config.username, &bot_emails);
+                state.shared_repo_check = Some(SharedRepoCheck { date: today, repo: self.config.repo.clone(), others: others.clone() });
+                self.save_state(&state)?;
+                others
+            }
+        };
+        if !others.is_empty() {
+            return Err(format!(
+                "Refusing to run: {} has commits by {} in the last {} days; set allow_shared_repo = true if the bot should run there anyway",
+                self.config.repo, others.join(", "), LOOKBACK_DAYS
+            ).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn only_people_other_than_the_user_and_the_bot_count() {
+        let commit = |login: Option<&str>, email: &str| json!({
+            "author": login.map(|login| json!({ "login": login })),
+            "commit": { "author": { "email": email } },
+        });
+        let commits = vec![
+            commit(Some("Octocat"), "me@example.com"),
+            commit(None, "bot@example.com"),
+            commit(Some("dependabot[bot]"), "support@github.com"),
+            commit(Some("hubot"), "hubot@example.com"),
+            commit(Some("hubot"), "hubot@example.com"),
+            commit(None, "colleague@example.com"),
+        ];
+
+        assert_eq!(other_committers(&commits, "octocat", &["bot@example.com"]), vec!["colleague@example.com", "hubot"]);
+        assert!(other_committers(&commits[..3], "octocat", &["bot@example.com"]).is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+use http::StatusCode;
+use rand::{rngs::StdRng, Rng};
+use std::fmt;
+
+use crate::GitHubBot;
+
+/// What `star_own_repo` did to the star on the repository
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StarChange {
+    Starred,
+    Unstarred,
+}
+
+impl fmt::Display for StarChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StarChange::Starred => write!(f, "starred"),
+            StarChange::Unstarred => write!(f, "unstarred"),
+        }
+    }
+}
+
+/// Star an unstarred repository with `star_probability`, or unstar a starred one with
+/// `unstar_probability`
+pub fn decide(starred: bool, star_probability: f64, unstar_probability: f64, rng: &mut StdRng) -> Option<StarChange> {
+    match starred {
+        false if star_probability > 0.0 && rng.gen_bool(star_probability) => Some(StarChange::Starred),
+        true if unstar_probability > 0.0 && rng.gen_bool(unstar_probability) => Some(StarChange::Unstarred),
+        _ => None,
+    }
+}
+
+impl GitHubBot {
+    /// Star or unstar the repository as the token's user, or leave it as it is
+    pub(crate) async fn cycle_star(&self, rng: &mut StdRng) -> Result<Option<StarChange>, Box<dyn std::error::Error>> {
+        let route = format!("/user/starred/{}/{}", self.repo_owner, self.repo_name);
+        let starred = match self.api.get_response(&route).await?.status() {
+            StatusCode::NO_CONTENT => true,
+            StatusCode::NOT_FOUND => false,
+            status => return Err(format!("Checking the star on {} returned {}", self.config.repo, status).into()),
+        };
+        let Some(change) = decide(starred, self.config.star_probability, self.config.unstar_probability, rng) else {
+            return Ok(None);
+        };
+
+        let response = match change {
+            StarChange::Starred => self.api.put_response(&route).await?,
+            StarChange::Unstarred => self.api.delete_response(&route).await?,
+        };
+        if !response.status().is_success() {
+            return Err(format!("Could not change the star on {}: {}", self.config.repo, response.status()).into());
+        }
+        println!("{} {}", if change == StarChange::Starred { "Starred" } else { "Unstarred" }, self.config.repo);
+        Ok(Some(change))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn stars_are_only_toggled_by_their_own_probability() {
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(decide(false, 1.0, 0.0, &mut rng), Some(StarChange::Starred));
+        assert_eq!(decide(true, 1.0, 0.0, &mut rng), None);
+        assert_eq!(decide(true, 0.0, 1.0, &mut rng), Some(StarChange::Unstarred));
+        assert_eq!(decide(false, 0.0, 1.0, &mut rng), None);
+        assert_eq!(StarChange::Unstarred.to_string(), "unstarred");
+    }
+}
@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use std::{fs, path::Path};
+
+use crate::shared_repo::SharedRepoCheck;
+use crate::streak::Streak;
+
+/// Persistent bot state kept between runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BotState {
+    /// Bot PRs that were left open for a human to merge
+    #[serde(default)]
+    pub open_prs: Vec<TrackedPr>,
+    /// The run in progress, cleared once it completes
+    #[serde(default)]
+    pub current_run: Option<RunState>,
+    /// Outcome of recent runs, oldest first
+    #[serde(default)]
+    pub run_history: Vec<RunRecord>,
+    /// Runs since the last analytics report was written
+    #[serde(default)]
+    pub runs_since_analytics: u32,
+    /// Runs since the CODEOWNERS file was last rewritten
+    #[serde(default)]
+    pub runs_since_codeowners: u32,
+    /// Last issue comment the ChatOps poller has seen
+    #[serde(default)]
+    pub chatops_cursor: Option<ChatOpsCursor>,
+    /// Days in a row the bot has contributed
+    #[serde(default)]
+    pub streak: Option<Streak>,
+    /// Runs since the changes directory was last compacted
+    #[serde(default)]
+    pub runs_since_compaction: u32,
+    /// Whether the compaction schedule asked the next run to compact
+    #[serde(default)]
+    pub compaction_requested: bool,
+    /// Today's result of the check for other contributors, unless `allow_shared_repo` is set
+    #[serde(default)]
+    pub shared_repo_check: Option<SharedRepoCheck>,
+}
+
+/// Where the ChatOps poller continues reading the issue's comments
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatOpsCursor {
+    pub last_comment_id: u64,
+    /// Creation time of that comment, passed as `since` to skip older pages
+    pub since: DateTime<Utc>,
+}
+
+/// Progress of the current run, saved after each phase so a crashed run can be resumed
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunState {
+    pub branch_name: String,
+    pub pr_number: Option<u64>,
+    /// Whether the branch reached GitHub; state saved before this field existed always was
+    #[serde(default = "default_true")]
+    pub pushed: bool,
+    /// Whether the PR was merged, leaving only the cleanup
+    #[serde(default)]
+    pub merged: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A bot PR whose branch must be deleted once the PR is merged or closed
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrackedPr {
+    pub number: u64,
+    pub branch_name: String,
+    pub opened_at: DateTime<Utc>,
+}
+
+/// Outcome of a finished run, kept for digests
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RunRecord {
+    /// Id of the run this record belongs to; a run that opens several PRs has several records
+    #[serde(default)]
+    pub run_id: Option<String>,
+    /// What the run worked on; records written before this field existed are all PR runs
+    #[serde(default)]
+    pub run_type: RunType,
+    pub started_at: DateTime<Utc>,
+    pub status: RunStatus,
+    #[serde(default)]
+    pub pr_number: Option<u64>,
+    #[serde(default)]
+    pub pr_url: Option<String>,
+    #[serde(default)]
+    pub merged: bool,
+    /// The PR was closed without merging, so it adds no merged-PR contribution
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub files_changed: usize,
+    #[serde(default)]
+    pub lines_changed: usize,
+    /// Lines the PR's diff adds and removes; not known for runs recorded before these fields existed
+    #[serde(default)]
+    pub lines_added: Option<usize>,
+    #[serde(default)]
+    pub lines_deleted: Option<usize>,
+    /// Seconds from opening the PR to merging it
+    #[serde(default)]
+    pub pr_open_secs: Option<i64>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Kind of activity a run produced
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunType {
+    /// A PR against the repository
+    #[default]
+    Pr,
+    /// A gist created or updated by `gist_activity`
+    Gist,
+    /// A description or topics change by `metadata_activity`
+    Metadata,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Succeeded,
+    Skipped,
+    Failed,
+    /// Aborted after `max_run_duration_secs`
+    #[serde(rename = "timed_out")]
+    TimedOut,
+}
+
+impl BotState {
+    /// Load the state file, or start with an empty state if it doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse state file {}: {}", path.display(), e).into())
+    }
+
+    /// Append a run to the history, dropping runs older than `retention`
+    pub fn record_run(&mut self, record: RunRecord, retention: chrono::Duration) {
+        let cutoff = record.started_at - retention;
+        self.run_history.retain(|run| run.started_at >= cutoff);
+        self.run_history.push(record);
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
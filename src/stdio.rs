@@ -0,0 +1,150 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+
+use crate::output::MachineOutput;
+use crate::{Config, GitHubBot, RunReport};
+
+tokio::task_local! {
+    /// Where a run started by `--serve-stdio` reports the phases it enters
+    static PROGRESS: mpsc::UnboundedSender<Value>;
+}
+
+/// Report that the current run entered `phase`; does nothing outside `--serve-stdio`
+pub fn phase_started(phase: &str) {
+    let _ = PROGRESS.try_with(|progress| progress.send(json!({ "event": "phase", "name": phase })));
+}
+
+/// A line read by `--serve-stdio`, e.g. `{"cmd":"run"}`
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Command {
+    Run,
+    Status,
+    Cleanup,
+    ReloadConfig,
+}
+
+/// The command on `line` and its optional `id`, which is echoed in the response; or the
+/// error response for a line that isn't valid JSON or names no known command
+fn parse_command(line: &str) -> Result<(Command, Option<Value>), Value> {
+    let request: Value = serde_json::from_str(line).map_err(|e| error_response(None, format!("invalid JSON: {}", e)))?;
+    let id = request.get("id").cloned();
+    match serde_json::from_value(request) {
+        Ok(command) => Ok((command, id)),
+        Err(e) => Err(error_response(id, format!("unknown command: {}", e))),
+    }
+}
+
+fn error_response(id: Option<Value>, error: String) -> Value {
+    with_id(json!({ "ok": false, "error": error }), id)
+}
+
+fn result_response(id: Option<Value>, result: Value) -> Value {
+    with_id(json!({ "ok": true, "result": result }), id)
+}
+
+fn with_id(mut response: Value, id: Option<Value>) -> Value {
+    if let Some(id) = id {
+        response["id"] = id;
+    }
+    response
+}
+
+/// What the response to `run` tells about the run
+fn report_json(report: &RunReport) -> Value {
+    json!({
+        "run_id": report.run_id,
+        "skipped": report.skipped,
+        "branch_name": report.branch_name,
+        "files_changed": report.files_changed,
+        "pr_number": report.pr_number,
+        "pr_url": report.pr_url,
+        "merged": report.merged,
+        "warnings": report.warnings,
+        "session": report.session.iter().map(report_json).collect::<Vec<_>>(),
+    })
+}
+
+/// Answer the newline-delimited JSON commands on stdin with one JSON line each on `out`,
+/// until stdin closes. `reload_config` rebuilds the bot from `reload`.
+pub async fn serve(
+    mut bot: GitHubBot,
+    reload: impl Fn() -> Result<Config, Box<dyn std::error::Error>>,
+    out: &mut MachineOutput,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (command, id) = match parse_command(&line) {
+            Ok(parsed) => parsed,
+            Err(response) => {
+                out.send(&response)?;
+                continue;
+            }
+        };
+
+        // Progress events are written as they come, before the command's response
+        let (progress, mut events) = mpsc::unbounded_channel();
+        let handled = PROGRESS.scope(progress, handle(&mut bot, command, &reload));
+        tokio::pin!(handled);
+        let response = loop {
+            tokio::select! {
+                response = &mut handled => break response,
+                Some(event) = events.recv() => out.send(&event)?,
+            }
+        };
+        while let Ok(event) = events.try_recv() {
+            out.send(&event)?;
+        }
+        let response = match response {
+            Ok(result) => result_response(id, result),
+            Err(e) => error_response(id, e.to_string()),
+        };
+        out.send(&response)?;
+    }
+    Ok(())
+}
+
+async fn handle(
+    bot: &mut GitHubBot,
+    command: Command,
+    reload: &impl Fn() -> Result<Config, Box<dyn std::error::Error>>,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    match command {
+        Command::Run => {
+            // Like scheduled runs, wait for a run triggered over HTTP to finish
+            let _running = bot.run_lock.lock().await;
+            Ok(report_json(&bot.run_and_record().await?))
+        }
+        Command::Status => Ok(json!(bot.check_health().await?)),
+        Command::Cleanup => Ok(json!({ "cleaned_up": bot.cleanup_tracked_prs().await? })),
+        Command::ReloadConfig => {
+            *bot = GitHubBot::new(reload()?).await?;
+            Ok(json!({ "repo": bot.config.repo }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_lines_get_an_error_response() {
+        assert_eq!(parse_command(r#"{"cmd":"run"}"#).unwrap(), (Command::Run, None));
+        assert_eq!(parse_command(r#"{"cmd":"reload_config","id":7}"#).unwrap(), (Command::ReloadConfig, Some(json!(7))));
+
+        let invalid = parse_command("{\"cmd\":").unwrap_err();
+        assert_eq!(invalid["ok"], false);
+        assert!(invalid["error"].as_str().unwrap().starts_with("invalid JSON"), "{}", invalid);
+
+        let unknown = parse_command(r#"{"cmd":"deploy","id":"a"}"#).unwrap_err();
+        assert!(unknown["error"].as_str().unwrap().starts_with("unknown command"), "{}", unknown);
+        assert_eq!(unknown["id"], "a");
+        assert!(parse_command(r#"{"id":1}"#).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone};
+use serde::{Serialize, Deserialize};
+use std::time::Instant;
+
+use crate::intensity::Intensity;
+use crate::GitHubBot;
+
+/// Consecutive days with a contribution, in `cron_timezone`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Streak {
+    pub started: NaiveDate,
+    pub last_contribution: NaiveDate,
+}
+
+impl Streak {
+    /// The streak after contributing on `day`: extended from yesterday, or restarted after a gap
+    pub fn record(streak: Option<Streak>, day: NaiveDate) -> Streak {
+        match streak {
+            Some(streak) if streak.last_contribution >= day => streak,
+            Some(streak) if streak.last_contribution.succ_opt() == Some(day) => {
+                Streak { started: streak.started, last_contribution: day }
+            }
+            _ => Streak { started: day, last_contribution: day },
+        }
+    }
+
+    /// Days in the streak, or 0 if it ended before `today`'s predecessor
+    pub fn days(&self, today: NaiveDate) -> i64 {
+        if (today - self.last_contribution).num_days() > 1 {
+            return 0;
+        }
+        (self.last_contribution - self.started).num_days() + 1
+    }
+}
+
+/// Parse a `catchup_window` such as "until 23:30"
+pub fn parse_catchup_window(window: &str) -> Result<NaiveTime, String> {
+    let time = window.trim().strip_prefix("until ")
+        .ok_or_else(|| format!("catchup_window '{}' must look like \"until 23:30\"", window))?;
+    NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .map_err(|_| format!("Invalid time '{}' in catchup_window", time.trim()))
+}
+
+/// One line on the streak for `--check-streak`, and whether `today` has a contribution
+pub fn streak_status(streak: Option<Streak>, today: NaiveDate) -> (String, bool) {
+    match streak {
+        Some(streak) if streak.last_contribution == today => {
+            (format!("Contributed today; streak of {} day(s) since {}", streak.days(today), streak.started), true)
+        }
+        Some(streak) => (
+            format!("No contribution yet today; last one on {}, streak of {} day(s)", streak.last_contribution, streak.days(today)),
+            false,
+        ),
+        None => ("No contribution recorded yet".to_string(), false),
+    }
+}
+
+impl GitHubBot {
+    /// Whether a catch-up run is due at `now`: `catchup_window` is set, today has no
+    /// contribution yet and the cutoff hasn't passed
+    pub(crate) fn catchup_due<Tz: TimeZone>(&self, now: DateTime<Tz>) -> Result<bool, Box<dyn std::error::Error>> {
+        let cutoff = match &self.config.catchup_window {
+            Some(window) => parse_catchup_window(window)?,
+            None => return Ok(false),
+        };
+        if now.time() >= cutoff {
+            return Ok(false);
+        }
+        let streak = self.load_state()?.streak;
+        Ok(streak.is_none_or(|streak| streak.last_contribution < now.date_naive()))
+    }
+
+    /// Run outside the cron cadence if today has no contribution yet, so the streak survives
+    /// the machine being off during the scheduled runs
+    pub(crate) async fn catch_up_streak(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.catchup_due(self.config.now())? {
+            return Ok(());
+        }
+        let _running = self.run_lock.lock().await;
+        // A scheduled or triggered run may have contributed while we waited
+        if !self.catchup_due(self.config.now())? {
+            return Ok(());
+        }
+
+        println!("No contribution yet today, starting a catch-up run");
+        // Today's intensity must not skip the run that keeps the streak
+        let mut bot = self.clone();
+        bot.config.intensity = Intensity::default();
+        let report = bot.run_and_record().await?;
+        *self.last_run_time.lock().unwrap() = Some(Instant::now());
+        println!("{}", report);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, day).unwrap()
+    }
+
+    #[test]
+    fn streaks_extend_on_consecutive_days_and_restart_after_gaps() {
+        let streak = Streak::record(None, day(1));
+        let streak = Streak::record(Some(streak), day(1));
+        let streak = Streak::record(Some(streak), day(2));
+        assert_eq!(streak, Streak { started: day(1), last_contribution: day(2) });
+        assert_eq!(streak.days(day(3)), 2);
+        assert_eq!(streak.days(day(4)), 0);
+
+        let streak = Streak::record(Some(streak), day(4));
+        assert_eq!(streak, Streak { started: day(4), last_contribution: day(4) });
+        assert!(streak_status(Some(streak), day(4)).1);
+        assert!(!streak_status(Some(streak), day(5)).1);
+    }
+
+    #[test]
+    fn catchup_window_is_an_until_time() {
+        assert_eq!(parse_catchup_window("until 23:30"), Ok(NaiveTime::from_hms_opt(23, 30, 0).unwrap()));
+        assert!(parse_catchup_window("23:30").is_err());
+        assert!(parse_catchup_window("until 25:00").is_err());
+    }
+}
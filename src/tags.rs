@@ -0,0 +1,108 @@
+use chrono::NaiveDate;
+use git2::{Oid, PushOptions};
+use std::path::Path;
+
+use crate::{changelog, client, GitHubBot};
+
+/// `{prefix}{YYYY.MM.DD}.{N}`, with `N` one more than the highest number of `date` among `existing`
+pub fn next_tag_name(prefix: &str, date: NaiveDate, existing: &[String]) -> String {
+    let day_prefix = format!("{}{}.", prefix, date.format("%Y.%m.%d"));
+    let last = existing
+        .iter()
+        .filter_map(|tag| tag.strip_prefix(&day_prefix)?.parse::<u32>().ok())
+        .max()
+        .unwrap_or(0);
+    format!("{}{}", day_prefix, last + 1)
+}
+
+impl GitHubBot {
+    /// The bot's tags in the local clone
+    pub(crate) fn bot_tags(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let repo = self.repository()?;
+        let pattern = format!("{}*", self.config.tag_prefix);
+        let names = repo.tag_names(Some(&pattern))?;
+        Ok(names.iter().flatten().map(str::to_string).collect())
+    }
+
+    /// Tag the merged base branch with an annotated `create_tag_on_merge` tag and push it.
+    /// Returns the tag's name.
+    pub(crate) async fn tag_merge(&self, pr_number: u64, title: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let base = self.pr_base()?.to_string();
+        // The remote's base has the merge commit, whichever branch the clone has checked out
+        let merged = self.fetch_branch(&base).await?;
+
+        let existing = self.bot_tags()?;
+        // The merged changelog already names the release, so the tag has to match it
+        let name = match self.changelog_release(merged, &existing)? {
+            Some(name) => name,
+            None => next_tag_name(&self.config.tag_prefix, self.config.today(), &existing),
+        };
+        let merged = merged.to_string();
+        let message = format!("{} (#{})", title, pr_number);
+
+        let mut env = Vec::new();
+        if let Some((name, email)) = self.commit_identity(&mut self.rng()) {
+            env.push(("GIT_COMMITTER_NAME", name.to_string()));
+            env.push(("GIT_COMMITTER_EMAIL", email.to_string()));
+        }
+        self.run_git_command_with_env(&["tag", "-a", &name, &merged, "-m", &message], env).await?;
+
+        let refspec = format!("refs/tags/{}", name);
+        if self.config.secure_auth {
+            let repo = self.repository()?;
+            let mut remote = repo.find_remote(&self.config.remote)?;
+            let mut push_options = PushOptions::new();
+            push_options.remote_callbacks(self.remote_callbacks());
+            if let Some(proxy_options) = client::git2_proxy_options(&self.config) {
+                push_options.proxy_options(proxy_options);
+            }
+            remote.push(&[format!("{}:{}", refspec, refspec).as_str()], Some(&mut push_options))
+                .map_err(|e| format!("Failed to push tag {}: {}", name, e.message()))?;
+        } else {
+            self.run_git_push(&["push", &self.config.remote, &refspec]).await?;
+        }
+
+        if self.config.debug {
+            println!("Tagged {} ({}) as {}", base, merged, name);
+        }
+        Ok(name)
+    }
+
+    /// The release heading of CHANGELOG.md at `commit`, if it names a bot tag that doesn't exist yet
+    pub(crate) fn changelog_release(&self, commit: Oid, existing: &[String]) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        if !self.config.changelog {
+            return Ok(None);
+        }
+        let repo = self.repository()?;
+        let tree = repo.find_commit(commit)?.tree()?;
+        let Ok(entry) = tree.get_path(Path::new(changelog::CHANGELOG_PATH)) else {
+            return Ok(None);
+        };
+        let blob = repo.find_blob(entry.id())?;
+        let content = String::from_utf8_lossy(blob.content());
+        let release = changelog::latest_release(&content)
+            .filter(|version| version.starts_with(&self.config.tag_prefix) && !existing.iter().any(|tag| tag == version))
+            .map(String::from);
+        Ok(release)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_are_numbered_per_day() {
+        let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        assert_eq!(next_tag_name("bot-v", date, &[]), "bot-v2024.05.01.1");
+
+        let existing = vec![
+            "bot-v2024.05.01.1".to_string(),
+            "bot-v2024.05.01.10".to_string(),
+            "bot-v2024.04.30.12".to_string(),
+            "bot-v2024.05.01.rc".to_string(),
+        ];
+        assert_eq!(next_tag_name("bot-v", date, &existing), "bot-v2024.05.01.11");
+        assert_eq!(next_tag_name("release-", date, &existing), "release-2024.05.01.1");
+    }
+}
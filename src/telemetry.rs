@@ -0,0 +1,151 @@
+use opentelemetry::trace::{FutureExt, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{trace as sdktrace, Resource};
+use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::RunReport;
+
+/// Name of the tracer the bot's spans come from
+const TRACER_NAME: &str = "github-activity-bot";
+
+/// Set once an exporter is installed; until then no span is even created
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The `[otel]` config section: where to export a trace of every run
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OtelConfig {
+    /// OTLP/gRPC endpoint of the collector, e.g. "http://localhost:4317"
+    pub endpoint: String,
+    /// `service.name` the traces are reported under
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+
+fn default_service_name() -> String {
+    TRACER_NAME.to_string()
+}
+
+/// Flushes the spans not exported yet when dropped
+pub struct TelemetryGuard;
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        global::shutdown_tracer_provider();
+    }
+}
+
+/// Install a batching OTLP exporter for the bot's spans. Spans are exported while the
+/// returned guard is alive.
+pub fn init(config: &OtelConfig) -> Result<TelemetryGuard, Box<dyn std::error::Error>> {
+    let resource = Resource::new([KeyValue::new("service.name", config.service_name.clone())]);
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(&config.endpoint))
+        .with_trace_config(sdktrace::config().with_resource(resource))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(TelemetryGuard)
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Attributes of the root span of a finished run
+fn report_attributes(report: &RunReport) -> Vec<KeyValue> {
+    let mut attributes = vec![KeyValue::new("merged", report.merged)];
+    if let Some(branch_name) = &report.branch_name {
+        attributes.push(KeyValue::new("branch", branch_name.clone()));
+    }
+    if let Some(pr_number) = report.pr_number {
+        attributes.push(KeyValue::new("pr_number", pr_number as i64));
+    }
+    if let Some(files_changed) = report.files_changed {
+        attributes.push(KeyValue::new("files_changed", files_changed as i64));
+    }
+    if let Some(skipped) = &report.skipped {
+        attributes.push(KeyValue::new("skipped", skipped.clone()));
+    }
+    attributes
+}
+
+fn fail(cx: &Context, error: &dyn fmt::Display) {
+    let span = cx.span();
+    span.set_attribute(KeyValue::new("error.message", error.to_string()));
+    span.set_status(Status::error(error.to_string()));
+}
+
+/// Run `run` as the root span of a new trace
+pub async fn traced_run(
+    run_id: &str,
+    repo: &str,
+    run: impl Future<Output = Result<RunReport, Box<dyn std::error::Error>>>,
+) -> Result<RunReport, Box<dyn std::error::Error>> {
+    if !enabled() {
+        return run.await;
+    }
+    let tracer = global::tracer(TRACER_NAME);
+    let span = tracer
+        .span_builder("run")
+        .with_attributes(vec![KeyValue::new("run_id", run_id.to_string()), KeyValue::new("repo", repo.to_string())])
+        .start_with_context(&tracer, &Context::new());
+    let cx = Context::new().with_span(span);
+
+    let result = run.with_context(cx.clone()).await;
+    match &result {
+        Ok(report) => {
+            for attribute in report_attributes(report) {
+                cx.span().set_attribute(attribute);
+            }
+        }
+        Err(e) => fail(&cx, e),
+    }
+    cx.span().end();
+    result
+}
+
+/// Run `future` as a child span named `name` of the current run, marking it failed on an error
+pub async fn phase<T, E: fmt::Display>(name: &'static str, future: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+    if !enabled() {
+        return future.await;
+    }
+    let cx = Context::current_with_span(global::tracer(TRACER_NAME).start(name));
+    let result = future.with_context(cx.clone()).await;
+    if let Err(e) = &result {
+        fail(&cx, e);
+    }
+    cx.span().end();
+    result
+}
+
+/// Run `future`, which can't fail, as a child span named `name` of the current run
+pub async fn step<T>(name: &'static str, future: impl Future<Output = T>) -> T {
+    phase(name, async { Ok::<T, std::convert::Infallible>(future.await) })
+        .await
+        .unwrap_or_else(|never| match never {})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn spans_are_skipped_without_an_exporter_and_carry_the_outcome() {
+        assert!(!enabled());
+        assert_eq!(phase("push", async { Err::<(), _>("rejected") }).await, Err("rejected"));
+        assert_eq!(step("wait", async { 3 }).await, 3);
+
+        let mut report = RunReport::new("run-1".to_string());
+        report.branch_name = Some("bot-update-1".to_string());
+        report.pr_number = Some(42);
+        report.files_changed = Some(3);
+        let attributes = report_attributes(&report);
+        let keys: Vec<&str> = attributes.iter().map(|attribute| attribute.key.as_str()).collect();
+        assert_eq!(keys, ["merged", "branch", "pr_number", "files_changed"]);
+        assert_eq!(attributes[2].value, 42i64.into());
+    }
+}
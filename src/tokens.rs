@@ -0,0 +1,132 @@
+use serde_json::Value;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{client, Config, GhApi};
+
+/// How long a token's remaining rate limit is trusted before `/rate_limit` is asked again
+const RATE_LIMIT_CACHE: Duration = Duration::from_secs(60);
+
+/// Tokens from `GITHUB_TOKENS` (comma-separated) or the environment variables named in
+/// `token_sources`; empty when neither is set
+pub fn configured_tokens(config: &Config) -> Result<Vec<String>, String> {
+    if let Ok(tokens) = std::env::var("GITHUB_TOKENS") {
+        return Ok(tokens.split(',').map(str::trim).filter(|token| !token.is_empty()).map(String::from).collect());
+    }
+    config.token_sources
+        .iter()
+        .map(|name| std::env::var(name).map_err(|_| format!("token_sources names {}, which is not set", name)))
+        .collect()
+}
+
+/// Several tokens of the same user, each run using the one with the most rate limit left
+pub struct TokenPool {
+    tokens: Vec<(String, GhApi)>,
+    /// Remaining calls of each token and when they were looked up
+    remaining: Mutex<Vec<Option<(u64, Instant)>>>,
+}
+
+impl TokenPool {
+    /// Build a client per token and check they all authenticate as the same user, since
+    /// commits by several accounts would split the contribution graph
+    pub async fn connect(config: &Config, tokens: Vec<String>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut clients = Vec::new();
+        let mut login: Option<String> = None;
+        for (index, token) in tokens.into_iter().enumerate() {
+            let api = GhApi::new(client::build_octocrab(config, &token)?);
+            let user: Value = api.get("/user").await
+                .map_err(|e| format!("Token #{} was rejected: {}", index + 1, e))?;
+            let user_login = user["login"].as_str().unwrap_or_default().to_string();
+            match &login {
+                Some(login) if !login.eq_ignore_ascii_case(&user_login) => {
+                    return Err(format!(
+                        "All tokens must belong to the same user, but token #1 is {} and token #{} is {}",
+                        login, index + 1, user_login,
+                    ).into());
+                }
+                Some(_) => {}
+                None => login = Some(user_login),
+            }
+            clients.push((token, api));
+        }
+        if clients.is_empty() {
+            return Err("The token pool needs at least one token".into());
+        }
+
+        let remaining = Mutex::new(vec![None; clients.len()]);
+        Ok(TokenPool { tokens: clients, remaining })
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// The token and client at `index`
+    pub fn get(&self, index: usize) -> (&str, &GhApi) {
+        let (token, api) = &self.tokens[index];
+        (token, api)
+    }
+
+    /// Index of the token with the most rate limit left, leaving out `excluded`.
+    /// A token whose limit can't be looked up is only picked when no other is left.
+    pub async fn pick(&self, excluded: &[usize]) -> Option<usize> {
+        let mut best: Option<(usize, u64)> = None;
+        for index in (0..self.len()).filter(|index| !excluded.contains(index)) {
+            let remaining = self.remaining(index).await.unwrap_or(0);
+            if best.is_none_or(|(_, most)| remaining > most) {
+                best = Some((index, remaining));
+            }
+        }
+        best.map(|(index, _)| index)
+    }
+
+    /// Remember that a token ran out, so it isn't picked until its limit is looked up again
+    pub fn mark_exhausted(&self, index: usize) {
+        self.remaining.lock().unwrap()[index] = Some((0, Instant::now()));
+    }
+
+    /// Remaining core rate limit of the token at `index`, cached for `RATE_LIMIT_CACHE`
+    async fn remaining(&self, index: usize) -> Option<u64> {
+        let cached = self.remaining.lock().unwrap()[index];
+        if let Some((remaining, checked_at)) = cached {
+            if checked_at.elapsed() < RATE_LIMIT_CACHE {
+                return Some(remaining);
+            }
+        }
+
+        let rate_limit: Value = match self.tokens[index].1.get("/rate_limit").await {
+            Ok(rate_limit) => rate_limit,
+            Err(e) => {
+                eprintln!("Warning: could not look up the rate limit of token #{}: {}", index + 1, e);
+                return None;
+            }
+        };
+        let remaining = rate_limit["resources"]["core"]["remaining"].as_u64()?;
+        self.remaining.lock().unwrap()[index] = Some((remaining, Instant::now()));
+        Some(remaining)
+    }
+}
+
+/// Whether an error is GitHub refusing a request over a primary or secondary rate limit
+pub fn is_rate_limited(error: &(dyn std::error::Error + 'static)) -> bool {
+    let message = match error.downcast_ref::<octocrab::Error>() {
+        Some(octocrab::Error::GitHub { source, .. }) => source.message.clone(),
+        _ => error.to_string(),
+    };
+    message.to_lowercase().contains("rate limit")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_errors_are_recognized_by_their_message() {
+        let primary: Box<dyn std::error::Error> = "Failed to create PR: API rate limit exceeded for user ID 1.".into();
+        let secondary: Box<dyn std::error::Error> = "You have exceeded a secondary rate limit. Please wait a few minutes".into();
+        let other: Box<dyn std::error::Error> = "Not Found".into();
+        assert!(is_rate_limited(primary.as_ref()));
+        assert!(is_rate_limited(secondary.as_ref()));
+        assert!(!is_rate_limited(other.as_ref()));
+    }
+}
@@ -0,0 +1,146 @@
+use chrono::Utc;
+use http::{Method, Request, Response, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Server};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+
+use crate::intensity::Intensity;
+use crate::{new_run_id, GitHubBot};
+
+/// Body of `POST /run`
+#[derive(Debug, Deserialize, Default)]
+struct RunRequest {
+    /// Run even when today's activity intensity would skip it
+    #[serde(default)]
+    force: bool,
+}
+
+/// Shared by the requests the trigger server handles
+pub struct TriggerServer {
+    bot: GitHubBot,
+    token: String,
+    /// Run started through `POST /run` that is still in progress
+    active_run: Mutex<Option<String>>,
+}
+
+impl TriggerServer {
+    pub fn new(bot: GitHubBot, token: String) -> Arc<Self> {
+        Arc::new(TriggerServer { bot, token, active_run: Mutex::new(None) })
+    }
+
+    /// Serve `POST /run`, `GET /runs/{id}` and `GET /health` on `addr` until the process exits
+    pub async fn serve(self: Arc<Self>, addr: SocketAddr) -> Result<(), hyper::Error> {
+        let make_service = make_service_fn(move |_| {
+            let server = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let server = server.clone();
+                    async move { Ok::<_, Infallible>(server.handle(request).await) }
+                }))
+            }
+        });
+        println!("Listening for run triggers on {}", addr);
+        Server::bind(&addr).serve(make_service).await
+    }
+
+    pub(crate) async fn handle(self: Arc<Self>, request: Request<Body>) -> Response<Body> {
+        let path = request.uri().path().to_string();
+        match (request.method(), path.as_str()) {
+            // Every route needs the token; `/health` calls the GitHub API and names the login
+            _ if !self.is_authorized(&request) => {
+                json_response(StatusCode::UNAUTHORIZED, json!({ "error": "missing or wrong bearer token" }))
+            }
+            (&Method::GET, "/health") => match self.bot.check_health().await {
+                Ok(health) => json_response(StatusCode::OK, json!(health)),
+                Err(e) => json_response(StatusCode::BAD_GATEWAY, json!({ "error": e.to_string() })),
+            },
+            (&Method::POST, "/run") => {
+                let body = match hyper::body::to_bytes(request.into_body()).await {
+                    Ok(body) => body,
+                    Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({ "error": e.to_string() })),
+                };
+                let run_request = if body.is_empty() {
+                    RunRequest::default()
+                } else {
+                    match serde_json::from_slice(&body) {
+                        Ok(run_request) => run_request,
+                        Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({ "error": e.to_string() })),
+                    }
+                };
+                self.start_run(run_request)
+            }
+            (&Method::GET, _) if path.starts_with("/runs/") => self.run_status(&path["/runs/".len()..]),
+            _ => json_response(StatusCode::NOT_FOUND, json!({ "error": "not found" })),
+        }
+    }
+
+    fn is_authorized(&self, request: &Request<Body>) -> bool {
+        request
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| bool::from(token.as_bytes().ct_eq(self.token.as_bytes())))
+    }
+
+    /// Start a run in the background unless one is already in progress
+    fn start_run(self: Arc<Self>, run_request: RunRequest) -> Response<Body> {
+        let guard = match self.bot.run_lock.clone().try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) => return json_response(StatusCode::CONFLICT, json!({ "error": "a run is already in progress" })),
+        };
+
+        let run_id = new_run_id(Utc::now());
+        *self.active_run.lock().unwrap() = Some(run_id.clone());
+        let mut bot = self.bot.clone();
+        if run_request.force {
+            bot.config.intensity = Intensity::default();
+        }
+        println!("Run {} triggered over HTTP{}", run_id, if run_request.force { " (forced)" } else { "" });
+
+        let server = self.clone();
+        let id = run_id.clone();
+        tokio::spawn(async move {
+            match bot.run_and_record_as(&id).await {
+                Ok(report) => println!("{}", report),
+                Err(e) => eprintln!("Error in bot run: {}", e),
+            }
+            *server.active_run.lock().unwrap() = None;
+            drop(guard);
+        });
+
+        json_response(StatusCode::ACCEPTED, json!({ "run_id": run_id }))
+    }
+
+    /// The run's records from the state file, or "running" while it is in progress
+    fn run_status(&self, run_id: &str) -> Response<Body> {
+        if self.active_run.lock().unwrap().as_deref() == Some(run_id) {
+            return json_response(StatusCode::OK, json!({ "run_id": run_id, "status": "running" }));
+        }
+        let state = match self.bot.load_state() {
+            Ok(state) => state,
+            Err(e) => return json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({ "error": e.to_string() })),
+        };
+        let records: Vec<_> = state.run_history
+            .iter()
+            .filter(|record| record.run_id.as_deref() == Some(run_id))
+            .collect();
+        match records.first() {
+            Some(first) => json_response(StatusCode::OK, json!({ "run_id": run_id, "status": first.status, "records": records })),
+            None => json_response(StatusCode::NOT_FOUND, json!({ "error": format!("no run {}", run_id) })),
+        }
+    }
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
@@ -0,0 +1,76 @@
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use std::f64::consts::PI;
+
+/// How random waits are spread between their `min` and `max`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WaitDistribution {
+    /// Every wait in the range is equally likely
+    #[default]
+    Uniform,
+    /// A bell curve around the middle of the range, which spans three standard deviations either side
+    Gaussian,
+    /// Mostly short waits just above `min` with a long tail towards `max`; the mean wait beyond `min` is `min` itself
+    Exponential,
+}
+
+/// A random wait from `min` to `max` seconds, spread by `distribution`
+pub fn sample_wait(min: u64, max: u64, distribution: WaitDistribution, rng: &mut impl Rng) -> u64 {
+    // Uniform waits draw exactly what they always did, so seeded runs stay the same
+    if distribution == WaitDistribution::Uniform {
+        return rng.gen_range(min..=max);
+    }
+    if min >= max {
+        return min;
+    }
+    // In (0, 1], so its logarithm is finite
+    let u = 1.0 - rng.gen::<f64>();
+    let secs = match distribution {
+        WaitDistribution::Gaussian => {
+            let mean = (min + max) as f64 / 2.0;
+            let std_dev = (max - min) as f64 / 6.0;
+            // Box-Muller transform
+            let z = (-2.0 * u.ln()).sqrt() * (2.0 * PI * rng.gen::<f64>()).cos();
+            mean + z * std_dev
+        }
+        WaitDistribution::Exponential | WaitDistribution::Uniform => {
+            let mean = min.max(1) as f64;
+            min as f64 - mean * u.ln()
+        }
+    };
+    secs.round().clamp(min as f64, max as f64) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn waits_stay_in_range_and_follow_their_distribution() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut samples = |distribution| {
+            let mut waits: Vec<u64> = (0..2000).map(|_| sample_wait(60, 180, distribution, &mut rng)).collect();
+            assert!(waits.iter().all(|wait| (60..=180).contains(wait)), "{:?}", distribution);
+            waits.sort();
+            waits
+        };
+
+        let gaussian = samples(WaitDistribution::Gaussian);
+        let median = gaussian[gaussian.len() / 2];
+        assert!((110..=130).contains(&median), "{}", median);
+        // Within one standard deviation of the middle about two thirds of the time
+        let near_middle = gaussian.iter().filter(|wait| (100..=140).contains(*wait)).count();
+        assert!((1200..=1500).contains(&near_middle), "{}", near_middle);
+
+        let exponential = samples(WaitDistribution::Exponential);
+        let median = exponential[exponential.len() / 2];
+        assert!((90..=115).contains(&median), "{}", median);
+
+        let mut uniform_rng = StdRng::seed_from_u64(7);
+        let mut expected_rng = StdRng::seed_from_u64(7);
+        assert_eq!(sample_wait(60, 180, WaitDistribution::Uniform, &mut uniform_rng), expected_rng.gen_range(60..=180));
+        assert_eq!(sample_wait(0, 0, WaitDistribution::Gaussian, &mut rng), 0);
+    }
+}
@@ -0,0 +1,188 @@
+use git2::{build::RepoBuilder, FetchOptions, IndexAddOption, PushOptions};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+use serde::{Serialize, Deserialize};
+use std::fs;
+
+use crate::{client, content, GitHubBot};
+
+/// One of the `[[wiki_pages]]` the bot rewrites
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WikiPageConfig {
+    /// Page title, e.g. "Release Notes"; the page is stored as `Release-Notes.md`
+    pub title: String,
+    /// Content of the page: "markdown", "timestamped", "json" or "rust"
+    #[serde(default = "default_content_strategy")]
+    pub content_strategy: String,
+}
+
+fn default_content_strategy() -> String {
+    "markdown".to_string()
+}
+
+/// Content strategies a wiki page can use, with the file extension that selects each
+const STRATEGIES: &[(&str, &str)] = &[("markdown", "md"), ("timestamped", "txt"), ("json", "json"), ("rust", "rs")];
+
+pub fn validate_wiki_pages(pages: &[WikiPageConfig]) -> Result<(), String> {
+    if pages.is_empty() {
+        return Err("update_wiki needs at least one entry in wiki_pages".to_string());
+    }
+    for page in pages {
+        if page.title.trim().is_empty() || page.title.contains(['/', '\\']) {
+            return Err(format!("wiki_pages title '{}' must be non-empty and contain no slashes", page.title));
+        }
+        if !STRATEGIES.iter().any(|(name, _)| *name == page.content_strategy) {
+            let names: Vec<&str> = STRATEGIES.iter().map(|(name, _)| *name).collect();
+            return Err(format!(
+                "wiki_pages content_strategy '{}' must be one of {}",
+                page.content_strategy,
+                names.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// File name of a wiki page in the wiki's repository, the way GitHub stores it
+pub fn page_file_name(title: &str) -> String {
+    format!("{}.md", title.trim().replace(' ', "-"))
+}
+
+/// Commit message like the one GitHub's wiki editor writes, naming the format of the page's file
+pub fn commit_message(title: &str, file_name: &str) -> String {
+    let format = match file_name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("md" | "markdown") => "markdown",
+        Some("textile") => "textile",
+        Some("rdoc") => "rdoc",
+        Some("org") => "org",
+        Some("adoc" | "asciidoc") => "asciidoc",
+        Some("wiki" | "mediawiki") => "mediawiki",
+        _ => return format!("Updated {}", title),
+    };
+    format!("Updated {} ({})", title, format)
+}
+
+/// Clone URL of the repository's wiki. Enterprise wikis live on the API's host without its `/api/v3` path.
+pub fn wiki_url(github_api_url: Option<&str>, repo: &str) -> String {
+    let host = match github_api_url {
+        Some(url) => url.trim_end_matches('/').trim_end_matches("/api/v3").trim_end_matches('/').to_string(),
+        None => "https://github.com".to_string(),
+    };
+    format!("{}/{}.wiki.git", host, repo)
+}
+
+/// Markdown body of a page written with `strategy`; code is fenced so the wiki renders it
+pub fn page_content(title: &str, strategy: &str, num_lines: usize, seeded: bool, rng: &mut StdRng) -> String {
+    let extension = STRATEGIES.iter().find(|(name, _)| *name == strategy).map_or("md", |(_, extension)| *extension);
+    let body = content::strategy_for(&format!("page.{}", extension), seeded).generate(num_lines, rng);
+    match extension {
+        "md" => body,
+        "json" | "rs" => format!("# {}\n\n```{}\n{}```\n", title, if extension == "rs" { "rust" } else { "json" }, body),
+        _ => format!("# {}\n\n{}", title, body),
+    }
+}
+
+impl GitHubBot {
+    /// Rewrite one random page of `wiki_pages` in the repository's wiki, returning its title.
+    /// The wiki is cloned into a temporary directory, since it's a repository of its own.
+    pub(crate) async fn update_wiki(&self, rng: &mut StdRng) -> Result<String, Box<dyn std::error::Error>> {
+        let page = self.config.wiki_pages.choose(rng).ok_or("wiki_pages is empty")?.clone();
+        let url = wiki_url(self.config.github_api_url.as_deref(), &self.config.repo);
+        let dir = std::env::temp_dir().join(format!("activity-bot-wiki-{}-{}", std::process::id(), rng.gen::<u32>()));
+        let num_lines = rng.gen_range(self.config.min_lines..=self.config.max_lines);
+        let text = page_content(&page.title, &page.content_strategy, num_lines, self.config.random_seed.is_some(), rng);
+        let identity = self.commit_identity(rng).map(|(name, email)| (name.to_string(), email.to_string()));
+
+        // Cloning, committing and pushing block, so they run off the async runtime
+        let (bot, clone_dir, title) = (self.clone(), dir.clone(), page.title.clone());
+        let written = tokio::task::spawn_blocking(move || {
+            bot.write_wiki_page(&url, &clone_dir, &title, &text, identity).map_err(|e| e.to_string())
+        }).await;
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            if self.config.debug {
+                println!("Could not remove the wiki clone at {}: {}", dir.display(), e);
+            }
+        }
+        written.map_err(|e| format!("Failed to update the wiki: {}", e))??;
+        println!("Updated wiki page \"{}\"", page.title);
+        Ok(page.title)
+    }
+
+    fn write_wiki_page(
+        &self,
+        url: &str,
+        dir: &std::path::Path,
+        title: &str,
+        text: &str,
+        identity: Option<(String, String)>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(self.remote_callbacks());
+        if let Some(proxy_options) = client::git2_proxy_options(&self.config) {
+            fetch_options.proxy_options(proxy_options);
+        }
+        // GitHub only creates the wiki's repository once its first page is saved on the website
+        let repo = RepoBuilder::new()
+            .fetch_options(fetch_options)
+            .clone(url, dir)
+            .map_err(|e| format!("Failed to clone the wiki (create its first page on GitHub to enable it): {}", e.message()))?;
+
+        let file_name = page_file_name(title);
+        fs::write(dir.join(&file_name), text)?;
+
+        let mut index = repo.index()?;
+        index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
+        index.write()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+        let signature = match identity {
+            Some((name, email)) => git2::Signature::now(&name, &email)?,
+            None => repo.signature()
+                .map_err(|e| format!("No commit identity configured (set user.name and user.email): {}", e.message()))?,
+        };
+        let head = repo.head()?;
+        let branch = head.shorthand().ok_or("Wiki branch name is not valid UTF-8")?.to_string();
+        let parent = head.peel_to_commit()?;
+        repo.commit(Some("HEAD"), &signature, &signature, &commit_message(title, &file_name), &tree, &[&parent])
+            .map_err(|e| format!("Failed to commit the wiki page: {}", e.message()))?;
+
+        let mut push_options = PushOptions::new();
+        push_options.remote_callbacks(self.remote_callbacks());
+        if let Some(proxy_options) = client::git2_proxy_options(&self.config) {
+            push_options.proxy_options(proxy_options);
+        }
+        let refspec = format!("refs/heads/{}:refs/heads/{}", branch, branch);
+        repo.find_remote("origin")?
+            .push(&[refspec.as_str()], Some(&mut push_options))
+            .map_err(|e| format!("Failed to push the wiki: {}", e.message()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn wiki_pages_map_to_files_and_urls() {
+        assert_eq!(page_file_name("Release Notes"), "Release-Notes.md");
+        assert_eq!(commit_message("Release Notes", "Release-Notes.md"), "Updated Release Notes (markdown)");
+        assert_eq!(commit_message("Notes", "Notes.textile"), "Updated Notes (textile)");
+        assert_eq!(commit_message("Notes", "Notes"), "Updated Notes");
+        assert_eq!(wiki_url(None, "octocat/activity"), "https://github.com/octocat/activity.wiki.git");
+        assert_eq!(
+            wiki_url(Some("https://ghe.example.com/api/v3/"), "octocat/activity"),
+            "https://ghe.example.com/octocat/activity.wiki.git"
+        );
+
+        let page = |title: &str, strategy: &str| WikiPageConfig { title: title.to_string(), content_strategy: strategy.to_string() };
+        assert!(validate_wiki_pages(&[page("Home", "markdown"), page("Data", "json")]).is_ok());
+        assert!(validate_wiki_pages(&[]).is_err());
+        assert!(validate_wiki_pages(&[page("docs/Home", "markdown")]).is_err());
+        assert!(validate_wiki_pages(&[page("Home", "yaml")]).is_err());
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let json = page_content("Data", "json", 10, true, &mut rng);
+        assert!(json.starts_with("# Data\n\n```json\n{"), "{}", json);
+        assert!(json.ends_with("}\n```\n"), "{}", json);
+    }
+}